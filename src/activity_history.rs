@@ -0,0 +1,255 @@
+use std::time::{Duration, Instant};
+
+/// Activity totals in fixed width time buckets, kept as cumulative sums so
+/// the total over the last `n` buckets is one subtraction.
+pub struct BucketRing {
+    width: Duration,
+    origin: Instant,
+    /// Running `(value, count)` totals at the end of each bucket, indexed by
+    /// bucket number modulo the capacity.
+    cumulative: Vec<(f64, usize)>,
+    /// The bucket the latest addition went into.
+    current: u64,
+    total: (f64, usize),
+}
+
+impl BucketRing {
+    pub fn new(width: Duration, capacity: usize, origin: Instant) -> BucketRing {
+        assert!(!width.is_zero() && capacity > 1);
+        BucketRing {
+            width,
+            origin,
+            cumulative: vec![(0.0, 0); capacity],
+            current: 0,
+            total: (0.0, 0),
+        }
+    }
+
+    pub fn width(&self) -> Duration {
+        self.width
+    }
+
+    /// How far back this ring can answer.
+    pub fn span(&self) -> Duration {
+        self.width * (self.cumulative.len() as u32 - 1)
+    }
+
+    fn bucket(&self, at: Instant) -> u64 {
+        (at.saturating_duration_since(self.origin).as_nanos() / self.width.as_nanos()) as u64
+    }
+
+    fn slot(&self, bucket: u64) -> usize {
+        (bucket % self.cumulative.len() as u64) as usize
+    }
+
+    /// Adds to the bucket `at` falls in. Additions are expected in time order,
+    /// anything older lands in the current bucket.
+    pub fn add(&mut self, at: Instant, value: f64) {
+        let bucket = self.bucket(at).max(self.current);
+        // Buckets nothing was added to carry the running total forward.
+        let skipped = (bucket - self.current).min(self.cumulative.len() as u64);
+        for b in bucket + 1 - skipped..bucket {
+            let slot = self.slot(b);
+            self.cumulative[slot] = self.total;
+        }
+        self.current = bucket;
+        self.total.0 += value;
+        self.total.1 += 1;
+        let slot = self.slot(bucket);
+        self.cumulative[slot] = self.total;
+    }
+
+    /// The total of the bucket `now` falls in and the `duration` before it,
+    /// rounded up to whole buckets and clamped to [`BucketRing::span`].
+    pub fn total_in_the_last(&self, now: Instant, duration: Duration) -> (f64, usize) {
+        let buckets = (duration.as_nanos().div_ceil(self.width.as_nanos()) as u64)
+            .min(self.cumulative.len() as u64 - 1);
+        let Some(before) = self.bucket(now).checked_sub(buckets + 1) else {
+            return self.total;
+        };
+        if before >= self.current {
+            return (0.0, 0);
+        }
+        let oldest = self
+            .current
+            .saturating_sub(self.cumulative.len() as u64 - 1);
+        let (value, count) = self.cumulative[self.slot(before.max(oldest))];
+        (self.total.0 - value, self.total.1 - count)
+    }
+}
+
+/// An exponential moving average of the activity rate, in activity per
+/// second.
+pub struct ActivityEma {
+    time_constant: Duration,
+    rate: f64,
+    last: Option<Instant>,
+}
+
+impl ActivityEma {
+    pub fn new(time_constant: Duration) -> ActivityEma {
+        ActivityEma {
+            time_constant,
+            rate: 0.0,
+            last: None,
+        }
+    }
+
+    fn decay(&self, now: Instant) -> f64 {
+        let Some(last) = self.last else {
+            return 0.0;
+        };
+        let elapsed = now.saturating_duration_since(last).as_secs_f64();
+        self.rate * (-elapsed / self.time_constant.as_secs_f64()).exp()
+    }
+
+    pub fn add(&mut self, at: Instant, value: f64) {
+        self.rate = self.decay(at) + value / self.time_constant.as_secs_f64();
+        self.last = Some(self.last.map_or(at, |last| last.max(at)));
+    }
+
+    pub fn rate(&self, now: Instant) -> f64 {
+        self.decay(now)
+    }
+}
+
+/// Per-second buckets for the last hour, per-minute buckets for the last day
+/// and an EMA on top.
+pub struct ActivityHistory {
+    seconds: BucketRing,
+    minutes: BucketRing,
+    ema: ActivityEma,
+}
+
+impl ActivityHistory {
+    pub fn new(origin: Instant) -> ActivityHistory {
+        ActivityHistory {
+            seconds: BucketRing::new(Duration::from_secs(1), 3601, origin),
+            minutes: BucketRing::new(Duration::from_secs(60), 24 * 60 + 1, origin),
+            ema: ActivityEma::new(Duration::from_secs(60)),
+        }
+    }
+
+    pub fn add(&mut self, at: Instant, value: f64) {
+        self.seconds.add(at, value);
+        self.minutes.add(at, value);
+        self.ema.add(at, value);
+    }
+
+    /// The activity value and number of activities in the last `duration`,
+    /// from the finest ring that reaches back that far.
+    pub fn value_in_the_last(&self, now: Instant, duration: Duration) -> (f64, usize) {
+        if duration <= self.seconds.span() {
+            self.seconds.total_in_the_last(now, duration)
+        } else {
+            self.minutes.total_in_the_last(now, duration)
+        }
+    }
+
+    pub fn ema_rate(&self, now: Instant) -> f64 {
+        self.ema.rate(now)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn window_totals() {
+        let origin = Instant::now();
+        let secs = |s: u64| origin + Duration::from_secs(s);
+        let mut ring = BucketRing::new(Duration::from_secs(1), 10, origin);
+        ring.add(secs(0), 1.0);
+        ring.add(secs(2), 2.0);
+        ring.add(secs(2), 3.0);
+        ring.add(secs(5), 4.0);
+
+        assert_eq!(ring.total_in_the_last(secs(5), Duration::ZERO), (4.0, 1));
+        assert_eq!(
+            ring.total_in_the_last(secs(5), Duration::from_secs(3)),
+            (9.0, 3)
+        );
+        assert_eq!(
+            ring.total_in_the_last(secs(5), Duration::from_secs(60)),
+            (10.0, 4)
+        );
+        assert_eq!(
+            ring.total_in_the_last(secs(7), Duration::from_secs(1)),
+            (0.0, 0)
+        );
+        // Older than the ring reaches, the first bucket has fallen off.
+        ring.add(secs(10), 5.0);
+        assert_eq!(
+            ring.total_in_the_last(secs(10), Duration::from_secs(60)),
+            (14.0, 4)
+        );
+        // Nothing for longer than the whole ring.
+        ring.add(secs(100), 6.0);
+        assert_eq!(
+            ring.total_in_the_last(secs(100), Duration::from_secs(60)),
+            (6.0, 1)
+        );
+    }
+
+    #[test]
+    fn ema_decays() {
+        let origin = Instant::now();
+        let mut ema = ActivityEma::new(Duration::from_secs(10));
+        ema.add(origin, 10.0);
+        assert_eq!(ema.rate(origin), 1.0);
+        let later = ema.rate(origin + Duration::from_secs(10));
+        assert!((later - (-1.0f64).exp()).abs() < 1e-9);
+    }
+
+    /// `activity_value_after` as it was before the buckets, for comparison.
+    fn linear_value_after(
+        data: &std::collections::VecDeque<(Instant, f64)>,
+        after: Instant,
+    ) -> f64 {
+        let index = data
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(i, (when, _))| (when < &after).then_some(i))
+            .unwrap_or(0);
+        data.iter().skip(index + 1).map(|(_, val)| val).sum()
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with --release --nocapture"]
+    fn bench_against_linear_scan() {
+        let origin = Instant::now();
+        let mut data = std::collections::VecDeque::with_capacity(4096);
+        let mut history = ActivityHistory::new(origin);
+        // 4096 samples at 100Hz, the old buffer size.
+        for i in 0..4096u64 {
+            let at = origin + Duration::from_millis(i * 10);
+            data.push_back((at, 1.0));
+            history.add(at, 1.0);
+        }
+        let now = origin + Duration::from_millis(4095 * 10);
+        let window = Duration::from_secs(30);
+        let queries = 100_000;
+
+        let start = Instant::now();
+        let mut linear = 0.0;
+        for _ in 0..queries {
+            linear += std::hint::black_box(linear_value_after(&data, now - window));
+        }
+        let linear_time = start.elapsed();
+
+        let start = Instant::now();
+        let mut bucketed = 0.0;
+        for _ in 0..queries {
+            bucketed += std::hint::black_box(history.value_in_the_last(now, window)).0;
+        }
+        let bucketed_time = start.elapsed();
+
+        println!("linear scan: {:?} per query", linear_time / queries);
+        println!("buckets:     {:?} per query", bucketed_time / queries);
+        // Buckets round the window up to whole seconds.
+        assert!((bucketed - linear).abs() / linear < 0.05);
+        assert!(bucketed_time < linear_time);
+    }
+}
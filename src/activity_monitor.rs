@@ -1,48 +1,215 @@
 use std::{
     collections::VecDeque,
+    sync::mpsc,
     time::{Duration, Instant},
 };
 
-use device_query::{DeviceEvents, DeviceQuery, DeviceState, Keycode};
+use device_query::Keycode;
 
-use crate::World;
+use anyhow::Result;
+
+use crate::{
+    activity_history::ActivityHistory,
+    config::ActivityConfig,
+    input_source::{self, IdleSource, InputSource},
+    sampler::{ActivitySampler, SamplerThread, Subscription},
+    scoring::ScoringProfile,
+    World,
+};
+
+/// Samples per second when nothing else is configured.
+pub const DEFAULT_SAMPLE_RATE_HZ: u32 = 50;
 
 pub type CalculateActivityFn = Box<dyn Fn(&ActivityMonitor, ActivityKind, usize) -> f64>;
 
 pub struct ActivityMonitor {
     max_data_buffer_size: usize,
     data: VecDeque<(ActivityKind, Instant, f64)>,
+    history: ActivityHistory,
     total_activity_value: f64,
     time_start: Instant,
-    previous_mouse_coord: (i32, i32),
-    previous_key_presses: Vec<Keycode>,
-    previous_mouse_pressses: Vec<bool>,
     calulate_activity_fn: CalculateActivityFn,
+    input: MonitorInput,
+    idle_source: Option<Box<dyn IdleSource>>,
+    now: Instant,
+    last_input: Instant,
+    idle_threshold: Duration,
+    idle_since: Option<Instant>,
+    idle_subscribers: Vec<mpsc::Sender<IdleTransition>>,
+}
+
+enum MonitorInput {
+    /// Sampled on every update, scored by the frame's delta.
+    Polled(ActivitySampler),
+    /// Sampled at a fixed rate on another thread, scored by the interval.
+    Subscribed(Subscription),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IdleTransition {
+    /// There's been no input since `since`.
+    IdleStart {
+        since: Instant,
+    },
+    IdleEnd {
+        idle_for: Duration,
+    },
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ActivityKind {
     KeyPress,
-    KeyJustPress,
+    KeyJustPress {
+        class: KeyClass,
+    },
+    /// Shift, control, alt and meta, which are counted apart from other keys.
+    ModifierPress,
+    ModifierJustPress,
     MousePressed,
     MouseJustPressed,
-    MouseMove { distance: f64 },
+    MouseMove {
+        distance: f64,
+    },
+    /// Moving with a mouse button held, like selecting text or dragging.
+    MouseDrag {
+        distance: f64,
+    },
+    /// The amount is in wheel steps.
+    Scroll,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyClass {
+    /// Letters, digits, punctuation and editing keys.
+    Typing,
+    /// Anything pressed with control, alt or meta held, and function keys.
+    Shortcut,
+    /// Arrows, page up and down and the like, which reading is mostly made of.
+    Navigation,
+}
+
+impl KeyClass {
+    pub fn of(key: Keycode, shortcut_modifier_held: bool) -> KeyClass {
+        use Keycode::*;
+        match key {
+            _ if shortcut_modifier_held => KeyClass::Shortcut,
+            F1 | F2 | F3 | F4 | F5 | F6 | F7 | F8 | F9 | F10 | F11 | F12 => KeyClass::Shortcut,
+            Up | Down | Left | Right | Home | End | PageUp | PageDown | Escape | Tab => {
+                KeyClass::Navigation
+            }
+            _ => KeyClass::Typing,
+        }
+    }
+}
+
+pub fn is_modifier(key: &Keycode) -> bool {
+    matches!(
+        key,
+        Keycode::LShift
+            | Keycode::RShift
+            | Keycode::LControl
+            | Keycode::RControl
+            | Keycode::LAlt
+            | Keycode::RAlt
+            | Keycode::Meta
+    )
 }
 
 impl ActivityMonitor {
-    pub fn new<A>(calulate_activity_fn: A, max_data_buffer_size: usize) -> ActivityMonitor
+    /// Subscribes to `sampler_thread`, which every monitor shares rather
+    /// than sampling the devices on its own.
+    pub fn new<A>(
+        sampler_thread: &SamplerThread,
+        calulate_activity_fn: A,
+        max_data_buffer_size: usize,
+    ) -> ActivityMonitor
+    where
+        A: Fn(&ActivityMonitor, ActivityKind, usize) -> f64 + 'static,
+    {
+        let mut activity_monitor = ActivityMonitor::with_subscription(
+            sampler_thread.subscribe(),
+            calulate_activity_fn,
+            max_data_buffer_size,
+        );
+        activity_monitor.idle_source = input_source::open_idle_source();
+        activity_monitor
+    }
+
+    /// Scores with the configured profile, subscribed to a shared sampler
+    /// started with [`ActivityMonitor::spawn_sampler`].
+    pub fn from_config(
+        config: &ActivityConfig,
+        sampler_thread: &SamplerThread,
+        max_data_buffer_size: usize,
+    ) -> Result<Self> {
+        let mut activity_monitor = ActivityMonitor::with_subscription(
+            sampler_thread.subscribe(),
+            config.scoring_profile()?.into_activity_fn(),
+            max_data_buffer_size,
+        );
+        activity_monitor.idle_source = input_source::open_idle_source();
+        Ok(activity_monitor)
+    }
+
+    /// Samples `input_source` on every update instead of on a thread.
+    pub fn with_input_source<S, A>(
+        input_source: S,
+        calulate_activity_fn: A,
+        max_data_buffer_size: usize,
+    ) -> ActivityMonitor
     where
+        S: InputSource + 'static,
         A: Fn(&ActivityMonitor, ActivityKind, usize) -> f64 + 'static,
     {
+        ActivityMonitor::with_input(
+            MonitorInput::Polled(ActivitySampler::new(input_source)),
+            calulate_activity_fn,
+            max_data_buffer_size,
+        )
+    }
+
+    pub fn spawn_sampler(config: &ActivityConfig) -> SamplerThread {
+        SamplerThread::spawn(config.input_backend, config.sample_rate_hz)
+    }
+
+    pub fn with_subscription<A>(
+        subscription: Subscription,
+        calulate_activity_fn: A,
+        max_data_buffer_size: usize,
+    ) -> ActivityMonitor
+    where
+        A: Fn(&ActivityMonitor, ActivityKind, usize) -> f64 + 'static,
+    {
+        ActivityMonitor::with_input(
+            MonitorInput::Subscribed(subscription),
+            calulate_activity_fn,
+            max_data_buffer_size,
+        )
+    }
+
+    fn with_input<A>(
+        input: MonitorInput,
+        calulate_activity_fn: A,
+        max_data_buffer_size: usize,
+    ) -> ActivityMonitor
+    where
+        A: Fn(&ActivityMonitor, ActivityKind, usize) -> f64 + 'static,
+    {
+        let now = Instant::now();
         ActivityMonitor {
             max_data_buffer_size,
             data: VecDeque::with_capacity(max_data_buffer_size),
+            history: ActivityHistory::new(now),
             total_activity_value: 0.0,
-            time_start: Instant::now(),
-            previous_mouse_coord: DeviceState.get_mouse().coords.into(),
-            previous_key_presses: DeviceState.get_keys(),
-            previous_mouse_pressses: DeviceState.get_mouse().button_pressed,
+            time_start: now,
             calulate_activity_fn: Box::new(calulate_activity_fn),
+            input,
+            idle_source: None,
+            now,
+            last_input: now,
+            idle_threshold: Duration::from_secs(60),
+            idle_since: None,
+            idle_subscribers: Vec::new(),
         }
     }
 
@@ -53,10 +220,25 @@ impl ActivityMonitor {
         self.calulate_activity_fn = Box::new(calulate_activity_fn)
     }
 
+    pub fn set_idle_source<I>(&mut self, idle_source: Option<I>)
+    where
+        I: IdleSource + 'static,
+    {
+        self.idle_source = idle_source.map(|i| Box::new(i) as _);
+    }
+
+    /// How long the system has seen no input at all, if the platform can
+    /// tell without polling devices.
+    pub fn system_idle_time(&mut self) -> Option<Duration> {
+        self.idle_source.as_mut()?.idle_time()
+    }
+
     pub fn activity_value(&self) -> f64 {
         self.total_activity_value
     }
 
+    /// The latest raw activities, up to the buffer size. Rate queries don't
+    /// scan this, they go through the bucketed history.
     pub fn data(&self) -> &VecDeque<(ActivityKind, Instant, f64)> {
         &self.data
     }
@@ -65,106 +247,340 @@ impl ActivityMonitor {
         self.time_start
     }
 
+    /// When input was last seen, regardless of how much data is buffered.
     pub fn time_last_active(&self) -> Instant {
-        self.data[self.data.len()].1
+        self.last_input
+    }
+
+    /// How long there's been no input, by this monitor's own sampling or the
+    /// system's idle time, whichever saw input more recently.
+    pub fn idle_duration(&mut self) -> Duration {
+        let own = self.now.saturating_duration_since(self.last_input);
+        match self.system_idle_time() {
+            Some(system) => own.min(system),
+            None => own,
+        }
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.idle_since.is_some()
+    }
+
+    /// How long without input counts as idle for [`IdleTransition`]s.
+    pub fn set_idle_threshold(&mut self, idle_threshold: Duration) {
+        self.idle_threshold = idle_threshold;
+    }
+
+    pub fn subscribe_idle(&mut self) -> mpsc::Receiver<IdleTransition> {
+        let (sender, receiver) = mpsc::channel();
+        self.idle_subscribers.push(sender);
+        receiver
+    }
+
+    fn update_idle(&mut self) {
+        let idle = self.idle_duration();
+        let transition = match (self.idle_since, idle >= self.idle_threshold) {
+            (None, true) => {
+                let since = self.now.checked_sub(idle).unwrap_or(self.time_start);
+                self.idle_since = Some(since);
+                IdleTransition::IdleStart { since }
+            }
+            (Some(since), false) => {
+                self.idle_since = None;
+                IdleTransition::IdleEnd {
+                    idle_for: self
+                        .now
+                        .checked_sub(idle)
+                        .unwrap_or(self.now)
+                        .saturating_duration_since(since),
+                }
+            }
+            _ => return,
+        };
+        self.idle_subscribers
+            .retain(|subscriber| subscriber.send(transition).is_ok());
     }
 
     pub fn activity_rate_in_the_last(&self, duration: Duration) -> (f64, usize) {
-        self.activity_value_after(Instant::now() - duration)
+        let duration = duration.min(self.now.saturating_duration_since(self.time_start));
+        self.history.value_in_the_last(self.now, duration)
     }
 
+    /// The activity since `after`, to the second.
     pub fn activity_value_after(&self, after: Instant) -> (f64, usize) {
-        if self.data.is_empty() {
-            return (0.0, 0);
-        }
+        self.activity_rate_in_the_last(self.now.saturating_duration_since(after))
+    }
 
-        let index_data_just_before_requested = self
-            .data
-            .iter()
-            .enumerate()
-            .rev()
-            .find_map(
-                |(i, (_, when, _))| {
-                    if when < &after {
-                        Some(i)
-                    } else {
-                        None
-                    }
-                },
-            )
-            .unwrap_or(0);
-        let data_range = self.data.iter().skip(index_data_just_before_requested + 1);
-        let len = data_range.len();
-        let rate: f64 = data_range.map(|(_, _, val)| val).sum::<f64>();
-        (rate, len)
+    /// The exponential moving average of activity per second.
+    pub fn activity_ema(&self) -> f64 {
+        self.history.ema_rate(self.now)
     }
 
     pub fn clear(&mut self) {
         self.data.clear();
+        self.history = ActivityHistory::new(self.now);
         self.total_activity_value = 0.0;
-        self.time_start = Instant::now();
+        self.time_start = self.now;
     }
 
     pub fn update_activity(&mut self, activity: ActivityKind, amount: usize, world: &World) {
-        let val = (self.calulate_activity_fn)(self, activity, amount) * world.delta().as_secs_f64();
+        self.add_activity(activity, amount, world.now(), world.delta());
+    }
+
+    /// Scores an activity seen at `at`, over `interval` since the sample
+    /// before it.
+    pub fn add_activity(
+        &mut self,
+        activity: ActivityKind,
+        amount: usize,
+        at: Instant,
+        interval: Duration,
+    ) {
+        let val = (self.calulate_activity_fn)(self, activity, amount) * interval.as_secs_f64();
         if self.data.len() == self.max_data_buffer_size {
             self.data.pop_front();
         }
-        self.data.push_back((activity, Instant::now(), val));
+        self.now = self.now.max(at);
+        self.last_input = self.last_input.max(at);
+        self.data.push_back((activity, at, val));
+        self.history.add(at, val);
         self.total_activity_value += val;
     }
 
-    fn update(&mut self, world: &World) {
-        let mouse = DeviceState.get_mouse();
-        let mouse_coord = mouse.coords.into();
-        let keys = DeviceState.get_keys();
-        let mouse_diff = {
-            let (px, py) = self.previous_mouse_coord;
-            let (nx, ny) = mouse_coord;
-            let (dx, dy) = ((px - nx) as f64, (py - ny) as f64);
-            (dx * dx + dy * dy).sqrt().abs()
-        };
-        let mouse_buttons_pressed = mouse.button_pressed.iter().filter(|v| **v).count();
-        let mouse_buttons_just_pressed = self
-            .previous_mouse_pressses
-            .iter()
-            .zip(mouse.button_pressed.iter())
-            .filter(|(&previous, &new)| !previous && new)
-            .count();
-        let keys_pressed = keys.len();
-        let keys_just_pressed = keys
-            .iter()
-            .filter(|k| !self.previous_key_presses.contains(k))
-            .count();
-
-        if mouse_buttons_pressed > 0 {
-            self.update_activity(ActivityKind::MousePressed, mouse_buttons_pressed, world);
-        }
-        if mouse_buttons_just_pressed > 0 {
-            self.update_activity(
-                ActivityKind::MouseJustPressed,
-                mouse_buttons_just_pressed,
-                world,
-            );
-        }
-        if mouse_diff > 0.0 {
-            self.update_activity(
-                ActivityKind::MouseMove {
-                    distance: mouse_diff,
-                },
-                1,
-                world,
-            )
-        }
-        if keys_pressed > 0 {
-            self.update_activity(ActivityKind::KeyPress, keys_pressed, world);
+    pub fn update(&mut self, world: &World) {
+        self.now = world.now();
+        match &mut self.input {
+            MonitorInput::Polled(sampler) => {
+                for (activity, amount) in sampler.sample() {
+                    self.add_activity(activity, amount, world.now(), world.delta());
+                }
+            }
+            MonitorInput::Subscribed(subscription) => {
+                let samples: Vec<_> = subscription.try_iter().collect();
+                for sample in samples {
+                    for (activity, amount) in sample.activities {
+                        self.add_activity(activity, amount, sample.at, sample.interval);
+                    }
+                }
+            }
         }
-        if keys_just_pressed > 0 {
-            self.update_activity(ActivityKind::KeyJustPress, keys_just_pressed, world);
+        self.update_idle();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        input_source::{InputSnapshot, ScriptedInput},
+        time::SimulatedClock,
+    };
+    use chrono::Utc;
+
+    fn monitor(snapshots: Vec<InputSnapshot>) -> ActivityMonitor {
+        ActivityMonitor::with_input_source(
+            ScriptedInput::new(snapshots),
+            ScoringProfile::default().into_activity_fn(),
+            16,
+        )
+    }
+
+    fn keys(keys: &[Keycode]) -> InputSnapshot {
+        InputSnapshot {
+            keys: keys.to_vec(),
+            ..Default::default()
         }
+    }
+
+    fn kinds(monitor: &ActivityMonitor) -> Vec<(ActivityKind, f64)> {
+        monitor.data().iter().map(|(k, _, v)| (*k, *v)).collect()
+    }
+
+    #[test]
+    fn key_just_pressed() {
+        let mut clock = SimulatedClock::new(Utc::now(), Duration::from_secs(1));
+        let mut monitor = monitor(vec![keys(&[Keycode::A]), keys(&[Keycode::A, Keycode::B])]);
+
+        monitor.update(&clock.tick());
+        assert_eq!(
+            kinds(&monitor),
+            vec![
+                (ActivityKind::KeyPress, 2.0),
+                (
+                    ActivityKind::KeyJustPress {
+                        class: KeyClass::Typing
+                    },
+                    75.0
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn held_key_only_scores_press() {
+        let mut clock = SimulatedClock::new(Utc::now(), Duration::from_millis(500));
+        let mut monitor = monitor(vec![keys(&[Keycode::A]), keys(&[Keycode::A])]);
+
+        monitor.update(&clock.tick());
+        monitor.update(&clock.tick());
+        assert_eq!(
+            kinds(&monitor),
+            vec![(ActivityKind::KeyPress, 0.5), (ActivityKind::KeyPress, 0.5)]
+        );
+        assert_eq!(monitor.activity_value(), 1.0);
+    }
+
+    #[test]
+    fn scroll_steps() {
+        let mut clock = SimulatedClock::new(Utc::now(), Duration::from_secs(1));
+        let mut monitor = monitor(vec![
+            InputSnapshot::default(),
+            InputSnapshot {
+                scroll: (-3, 1),
+                ..Default::default()
+            },
+        ]);
+
+        monitor.update(&clock.tick());
+        assert_eq!(kinds(&monitor), vec![(ActivityKind::Scroll, 40.0)]);
+    }
+
+    #[test]
+    fn mouse_distance() {
+        let mut clock = SimulatedClock::new(Utc::now(), Duration::from_secs(1));
+        let mut monitor = monitor(vec![
+            InputSnapshot {
+                mouse_buttons: vec![false, false],
+                ..Default::default()
+            },
+            InputSnapshot {
+                mouse_coord: (30, 40),
+                mouse_buttons: vec![false, false],
+                ..Default::default()
+            },
+        ]);
+
+        monitor.update(&clock.tick());
+        assert_eq!(
+            kinds(&monitor),
+            vec![(ActivityKind::MouseMove { distance: 50.0 }, 5.0)]
+        );
+    }
+
+    #[test]
+    fn mouse_drag() {
+        let mut clock = SimulatedClock::new(Utc::now(), Duration::from_secs(1));
+        let mut monitor = monitor(vec![
+            InputSnapshot {
+                mouse_buttons: vec![false, false],
+                ..Default::default()
+            },
+            InputSnapshot {
+                mouse_coord: (30, 40),
+                mouse_buttons: vec![false, true],
+                ..Default::default()
+            },
+        ]);
+
+        monitor.update(&clock.tick());
+        assert_eq!(
+            kinds(&monitor),
+            vec![
+                (ActivityKind::MousePressed, 1.0),
+                (ActivityKind::MouseJustPressed, 75.0),
+                (ActivityKind::MouseDrag { distance: 50.0 }, 10.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn key_classes() {
+        let mut clock = SimulatedClock::new(Utc::now(), Duration::from_secs(1));
+        let mut monitor = monitor(vec![
+            keys(&[]),
+            keys(&[Keycode::LShift, Keycode::A, Keycode::PageDown]),
+            keys(&[Keycode::LControl]),
+            keys(&[Keycode::LControl, Keycode::C]),
+        ]);
+
+        monitor.update(&clock.tick());
+        monitor.update(&clock.tick());
+        monitor.update(&clock.tick());
+        let just_pressed: Vec<_> = kinds(&monitor)
+            .into_iter()
+            .filter_map(|(kind, _)| match kind {
+                ActivityKind::KeyJustPress { class } => Some(class),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            just_pressed,
+            vec![KeyClass::Typing, KeyClass::Navigation, KeyClass::Shortcut]
+        );
+    }
+
+    #[test]
+    fn idle_transitions() {
+        let mut monitor = monitor(vec![
+            InputSnapshot::default(),
+            InputSnapshot::default(),
+            InputSnapshot::default(),
+            InputSnapshot::default(),
+            keys(&[Keycode::A]),
+        ]);
+        let mut clock = SimulatedClock::new(Utc::now(), Duration::from_secs(1));
+        monitor.set_idle_threshold(Duration::from_secs(2));
+        let transitions = monitor.subscribe_idle();
+
+        monitor.update(&clock.tick());
+        assert!(transitions.try_recv().is_err());
+        monitor.update(&clock.tick());
+        monitor.update(&clock.tick());
+        assert!(matches!(
+            transitions.try_recv(),
+            Ok(IdleTransition::IdleStart { .. })
+        ));
+        assert!(monitor.is_idle());
+        assert!(transitions.try_recv().is_err());
+
+        monitor.update(&clock.tick());
+        assert_eq!(monitor.time_last_active(), clock.now());
+        assert!(matches!(
+            transitions.try_recv(),
+            Ok(IdleTransition::IdleEnd { idle_for }) if idle_for >= Duration::from_secs(3)
+        ));
+        assert!(!monitor.is_idle());
+    }
 
-        self.previous_mouse_pressses = mouse.button_pressed;
-        self.previous_mouse_coord = mouse_coord;
-        self.previous_key_presses = keys;
+    #[test]
+    fn sampler_thread_scores_by_interval() {
+        let sampler_thread = SamplerThread::spawn_with(
+            || Ok(ScriptedInput::new(vec![keys(&[]), keys(&[Keycode::A])])),
+            100,
+        );
+        let mut monitor = ActivityMonitor::with_subscription(
+            sampler_thread.subscribe(),
+            ScoringProfile::default().into_activity_fn(),
+            16,
+        );
+        // Samples go out to every subscriber at once, so by the time this
+        // one has the key press the monitor's has too.
+        let probe = sampler_thread.subscribe();
+        probe.recv_timeout(Duration::from_secs(5)).unwrap();
+        // However long the frame took.
+        let mut clock = SimulatedClock::new(Utc::now(), Duration::from_secs(5));
+        monitor.update(&clock.tick());
+        assert_eq!(
+            kinds(&monitor)[..2],
+            [
+                (ActivityKind::KeyPress, 0.01),
+                (
+                    ActivityKind::KeyJustPress {
+                        class: KeyClass::Typing
+                    },
+                    0.75
+                )
+            ]
+        );
     }
 }
@@ -1,20 +1,34 @@
 use std::sync::mpsc;
 use std::time::Duration;
 
+use anyhow::Result;
+
 use crate::{
     activity_monitor::{ActivityKind, ActivityMonitor},
+    config::{ActivityConfig, BreakConfig},
+    sampler::SamplerThread,
     time::{Stopwatch, Timer},
     World,
 };
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BreakState {
     Break,
     NotBreak,
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct LongBreak {
+    pub duration: Duration,
+    /// Every n-th break is a long one.
+    pub every: u32,
+}
+
 pub struct BasicTimeBreak {
     break_duration: Duration,
+    long_break: Option<LongBreak>,
+    breaks_started: u32,
+    hold_break: bool,
     break_timer: Stopwatch,
     not_break_duration: Duration,
     not_break_timer: Stopwatch,
@@ -32,6 +46,9 @@ impl BasicTimeBreak {
     ) -> BasicTimeBreak {
         BasicTimeBreak {
             break_duration,
+            long_break: None,
+            breaks_started: 0,
+            hold_break: false,
             not_break_duration,
             not_break_timer: Stopwatch::new(),
             break_timer: Stopwatch::new(),
@@ -42,11 +59,63 @@ impl BasicTimeBreak {
         }
     }
 
+    pub fn from_config(config: &BreakConfig) -> BasicTimeBreak {
+        let mut break_notifier = BasicTimeBreak::new(
+            BreakState::NotBreak,
+            config.break_duration(),
+            config.work_duration(),
+        );
+        if config.long_break_every > 0 {
+            break_notifier.set_long_break(Some(LongBreak {
+                duration: config.long_break_duration(),
+                every: config.long_break_every,
+            }));
+        }
+        break_notifier
+    }
+
+    pub fn set_long_break(&mut self, long_break: Option<LongBreak>) {
+        self.long_break = long_break;
+    }
+
+    /// Whether the current break, or the upcoming one if working, is long.
+    pub fn is_long_break(&self) -> bool {
+        let number = match self.state {
+            BreakState::Break => self.breaks_started,
+            BreakState::NotBreak => self.breaks_started + 1,
+        };
+        match self.long_break {
+            Some(long_break) if long_break.every > 0 => number % long_break.every == 0,
+            _ => false,
+        }
+    }
+
+    pub fn current_break_duration(&self) -> Duration {
+        match self.long_break {
+            Some(long_break) if self.is_long_break() => long_break.duration,
+            _ => self.break_duration,
+        }
+    }
+
+    /// Whether working time is up, or will be once `world` is applied.
+    pub fn is_break_due(&self, world: &World) -> bool {
+        self.state == BreakState::NotBreak
+            && self.not_break_timer.time() + world.delta() >= self.not_break_duration
+    }
+
+    /// Keeps a due break from starting.
+    pub fn set_hold_break(&mut self, hold: bool) {
+        self.hold_break = hold;
+    }
+
     pub fn time_before_start_break(&self) -> Option<Duration> {
         if self.not_break_timer.pause {
             None
         } else {
-            Some(self.not_break_duration - self.not_break_timer.time())
+            Some(
+                self.not_break_duration
+                    .saturating_sub(self.not_break_timer.time()),
+            )
         }
     }
 
@@ -54,7 +123,10 @@ impl BasicTimeBreak {
         if self.break_timer.pause {
             None
         } else {
-            Some(self.break_duration - self.break_timer.time())
+            Some(
+                self.current_break_duration()
+                    .saturating_sub(self.break_timer.time()),
+            )
         }
     }
 
@@ -70,6 +142,7 @@ impl BasicTimeBreak {
         self.state = state;
         match state {
             BreakState::Break => {
+                self.breaks_started += 1;
                 self.not_break_timer.pause = true;
                 self.break_timer.restart();
             }
@@ -80,11 +153,22 @@ impl BasicTimeBreak {
         }
     }
 
+    /// Picks up a break that ran for `elapsed` before the app stopped. This
+    /// will not call the callback either.
+    pub fn resume_break(&mut self, elapsed: Duration, long: bool) {
+        if let Some(long_break) = self.long_break.filter(|long_break| long_break.every > 0) {
+            // Line the count up so the resumed break is the same kind.
+            self.breaks_started = if long { long_break.every - 1 } else { 0 };
+        }
+        self.switch_to(BreakState::Break);
+        self.break_timer.advance(elapsed);
+    }
+
     pub fn update(&mut self, world: &World) {
         match self.state {
             BreakState::Break => {
                 self.break_timer.update(world);
-                if self.break_timer.time() > self.break_duration {
+                if self.break_timer.time() >= self.current_break_duration() {
                     self.break_timer.pause = true;
                     if let Some(f) = self.end_break_callback.as_ref() {
                         f()
@@ -95,7 +179,8 @@ impl BasicTimeBreak {
             }
             BreakState::NotBreak => {
                 self.not_break_timer.update(world);
-                if self.not_break_timer.time() > self.not_break_duration {
+                if self.not_break_timer.time() >= self.not_break_duration && !self.hold_break {
+                    self.breaks_started += 1;
                     self.not_break_timer.pause = true;
                     if let Some(f) = self.start_break_callback.as_ref() {
                         f()
@@ -126,6 +211,11 @@ impl BasicTimeBreak {
     }
 }
 
+/// How far back the activity level is averaged.
+const ACTIVITY_LEVEL_WINDOW: Duration = Duration::from_secs(60);
+
+/// Calls for a break after a long enough stretch of high activity. Enough
+/// time at a low activity level counts as a break already taken.
 pub struct ActivityBreak {
     activity_monitor: ActivityMonitor,
 
@@ -134,39 +224,145 @@ pub struct ActivityBreak {
     current_consecutive_high_acticity_level: Stopwatch,
 
     break_duration: Duration,
+    break_timer: Stopwatch,
+    low_activity_level: f64,
     duration_count_as_idle: Duration,
     current_idle_duration: Stopwatch,
 
     state: BreakState,
+
+    start_break_callback: Option<Box<dyn Fn()>>,
+    end_break_callback: Option<Box<dyn Fn()>>,
 }
 
 impl ActivityBreak {
-    pub fn new(
+    /// Set up from `config`, scoring samples from the app's shared sampler
+    /// started with [`ActivityMonitor::spawn_sampler`].
+    pub fn new(config: &ActivityConfig, sampler_thread: &SamplerThread) -> Result<ActivityBreak> {
+        let activity_monitor = ActivityMonitor::from_config(config, sampler_thread, 4096)?;
+        Ok(ActivityBreak::from_config(config, activity_monitor))
+    }
+
+    /// `threshold` is the activity level below which time counts as idle.
+    pub fn with_activity_monitor(
         in_state: BreakState,
+        mut activity_monitor: ActivityMonitor,
         high_activity_level: f64,
         threshold: f64,
         duration: Duration,
         duration_count_as_idle: Duration,
         break_duration: Duration,
     ) -> ActivityBreak {
+        activity_monitor.set_idle_threshold(duration_count_as_idle);
         ActivityBreak {
-            activity_monitor: ActivityMonitor::new(
-                |_, activity_kind, amount| match activity_kind {
-                    ActivityKind::KeyPress => 1.0 * amount as f64,
-                    ActivityKind::KeyJustPress => 75.0 * amount as f64,
-                    ActivityKind::MousePressed => 1.0 * amount as f64,
-                    ActivityKind::MouseJustPressed => 75.0 * amount as f64,
-                    ActivityKind::MouseMove { distance } => distance / 10.0 * amount as f64,
-                },
-                4096,
-            ),
+            activity_monitor,
             high_activity_level,
             consecutive_high_activity_level_duration: duration,
             current_consecutive_high_acticity_level: Stopwatch::new(),
             break_duration,
+            break_timer: Stopwatch::new(),
+            low_activity_level: threshold,
             duration_count_as_idle,
             current_idle_duration: Stopwatch::new(),
             state: in_state,
+
+            start_break_callback: None,
+            end_break_callback: None,
         }
     }
+
+    pub fn from_config(
+        config: &ActivityConfig,
+        activity_monitor: ActivityMonitor,
+    ) -> ActivityBreak {
+        ActivityBreak::with_activity_monitor(
+            BreakState::NotBreak,
+            activity_monitor,
+            config.high_activity_level,
+            config.low_activity_level,
+            config.active_duration(),
+            config.idle_duration(),
+            config.break_duration(),
+        )
+    }
+
+    pub fn activity_monitor(&self) -> &ActivityMonitor {
+        &self.activity_monitor
+    }
+
+    pub fn activity_monitor_mut(&mut self) -> &mut ActivityMonitor {
+        &mut self.activity_monitor
+    }
+
+    /// Activity per second, averaged over the last minute.
+    pub fn activity_level(&self) -> f64 {
+        let (value, _) = self
+            .activity_monitor
+            .activity_rate_in_the_last(ACTIVITY_LEVEL_WINDOW);
+        value / ACTIVITY_LEVEL_WINDOW.as_secs_f64()
+    }
+
+    /// How long activity has been high without a long enough idle stretch.
+    pub fn time_active(&self) -> Duration {
+        self.current_consecutive_high_acticity_level.time()
+    }
+
+    pub fn break_state(&self) -> BreakState {
+        self.state
+    }
+
+    pub fn update(&mut self, world: &World) {
+        self.activity_monitor.update(world);
+        match self.state {
+            BreakState::Break => {
+                self.break_timer.update(world);
+                if self.break_timer.time() >= self.break_duration {
+                    self.break_timer.pause = true;
+                    if let Some(f) = self.end_break_callback.as_ref() {
+                        f()
+                    }
+                    self.current_consecutive_high_acticity_level.reset();
+                    self.current_idle_duration.reset();
+                    self.state = BreakState::NotBreak;
+                }
+            }
+            BreakState::NotBreak => {
+                let level = self.activity_level();
+                if level >= self.high_activity_level {
+                    self.current_consecutive_high_acticity_level.update(world);
+                }
+                if level < self.low_activity_level {
+                    self.current_idle_duration.update(world);
+                    if self.current_idle_duration.time() >= self.duration_count_as_idle {
+                        self.current_consecutive_high_acticity_level.reset();
+                    }
+                } else {
+                    self.current_idle_duration.reset();
+                }
+                if self.current_consecutive_high_acticity_level.time()
+                    >= self.consecutive_high_activity_level_duration
+                {
+                    if let Some(f) = self.start_break_callback.as_ref() {
+                        f()
+                    }
+                    self.break_timer.restart();
+                    self.state = BreakState::Break;
+                }
+            }
+        }
+    }
+
+    pub fn set_start_break_callback<F>(&mut self, f: Option<F>)
+    where
+        F: Fn() + 'static,
+    {
+        self.start_break_callback = f.map(|f| Box::new(f) as _)
+    }
+
+    pub fn set_end_break_callback<F>(&mut self, f: Option<F>)
+    where
+        F: Fn() + 'static,
+    {
+        self.end_break_callback = f.map(|f| Box::new(f) as _)
+    }
 }
@@ -0,0 +1,693 @@
+//! Busy times from local iCalendar files.
+//!
+//! Only what's needed to tell whether a meeting is going on is understood:
+//! timed `VEVENT`s with `DTSTART` and `DTEND` or `DURATION`, plus simple
+//! `DAILY`/`WEEKLY` recurrence rules and `EXDATE`s. All-day, cancelled and
+//! transparent events never count as busy.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{
+    DateTime, Datelike, Duration as ChronoDuration, NaiveDate, NaiveDateTime, TimeZone, Utc,
+};
+
+use crate::{
+    deferral::{Busy, BusySource},
+    schedule::{Day, ScheduleTimeZone},
+};
+
+/// How often the files are checked for changes.
+const RELOAD_INTERVAL_SECS: i64 = 60;
+/// How far around now occurrences of recurring events are expanded.
+const HORIZON_HOURS: i64 = 48;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    pub summary: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+struct EventDefinition {
+    summary: String,
+    time_zone: ScheduleTimeZone,
+    start: NaiveDateTime,
+    duration: ChronoDuration,
+    recurrence: Option<Recurrence>,
+    exceptions: Vec<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+struct Recurrence {
+    weekly: bool,
+    interval: u32,
+    count: Option<u32>,
+    until: Option<DateTime<Utc>>,
+    /// Filters days for daily rules, picks them for weekly ones.
+    by_day: Day,
+}
+
+impl EventDefinition {
+    fn occurrences(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<Event> {
+        let event_at = |start: NaiveDateTime| {
+            let start = self.time_zone.resolve(start);
+            Event {
+                summary: self.summary.clone(),
+                start,
+                end: start + self.duration,
+            }
+        };
+        let Some(recurrence) = &self.recurrence else {
+            let event = event_at(self.start);
+            return if event.end > from && event.start < to {
+                vec![event]
+            } else {
+                Vec::new()
+            };
+        };
+
+        let mut events = Vec::new();
+        let mut produced = 0;
+        let mut period_start = self.start.date();
+        let step = if recurrence.weekly {
+            ChronoDuration::weeks(recurrence.interval as i64)
+        } else {
+            ChronoDuration::days(recurrence.interval as i64)
+        };
+        // Weekly rules walk whole weeks starting on the week of DTSTART.
+        if recurrence.weekly {
+            period_start -=
+                ChronoDuration::days(period_start.weekday().num_days_from_sunday() as i64);
+        }
+        loop {
+            let days: Vec<NaiveDate> = if recurrence.weekly {
+                (0..7)
+                    .map(|offset| period_start + ChronoDuration::days(offset))
+                    .filter(|date| {
+                        recurrence
+                            .by_day
+                            .contains(Day::from_weekday(date.weekday()))
+                    })
+                    .collect()
+            } else {
+                Some(period_start)
+                    .filter(|date| {
+                        recurrence
+                            .by_day
+                            .contains(Day::from_weekday(date.weekday()))
+                    })
+                    .into_iter()
+                    .collect()
+            };
+            for date in days {
+                let start = date.and_time(self.start.time());
+                if start < self.start {
+                    continue;
+                }
+                if recurrence.count.is_some_and(|count| produced >= count) {
+                    return events;
+                }
+                let event = event_at(start);
+                if recurrence.until.is_some_and(|until| event.start > until) || event.start >= to {
+                    return events;
+                }
+                produced += 1;
+                if self.exceptions.contains(&event.start) {
+                    continue;
+                }
+                if event.end > from {
+                    events.push(event);
+                }
+            }
+            period_start += step;
+        }
+    }
+}
+
+/// Parses every `VEVENT` out of an iCalendar document.
+fn parse_calendar(content: &str) -> Result<Vec<EventDefinition>> {
+    // Lines starting with whitespace continue the previous one.
+    let mut lines: Vec<String> = Vec::new();
+    for line in content.lines() {
+        match line.strip_prefix([' ', '\t']) {
+            Some(continuation) if !lines.is_empty() => {
+                lines.last_mut().unwrap().push_str(continuation)
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    let mut definitions = Vec::new();
+    let mut event: Option<Vec<Property>> = None;
+    let mut nested = 0;
+    for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+        let property = Property::parse(line)?;
+        match (property.name.as_str(), property.value.as_str()) {
+            ("BEGIN", "VEVENT") => event = Some(Vec::new()),
+            ("END", "VEVENT") => {
+                if let Some(properties) = event.take() {
+                    if let Some(definition) = event_definition(&properties)? {
+                        definitions.push(definition);
+                    }
+                }
+            }
+            // Alarms inside events carry their own DURATION and the like.
+            ("BEGIN", _) if event.is_some() => nested += 1,
+            ("END", _) if event.is_some() => nested -= 1,
+            _ => {
+                if let (Some(properties), 0) = (event.as_mut(), nested) {
+                    properties.push(property);
+                }
+            }
+        }
+    }
+    Ok(definitions)
+}
+
+fn event_definition(properties: &[Property]) -> Result<Option<EventDefinition>> {
+    let get = |name: &str| properties.iter().find(|p| p.name == name);
+    if get("STATUS").is_some_and(|p| p.value == "CANCELLED")
+        || get("TRANSP").is_some_and(|p| p.value == "TRANSPARENT")
+    {
+        return Ok(None);
+    }
+    let Some(dtstart) = get("DTSTART") else {
+        return Ok(None);
+    };
+    let Some((start, time_zone)) = dtstart.date_time()? else {
+        return Ok(None);
+    };
+    let duration = if let Some(dtend) = get("DTEND") {
+        match dtend.date_time()? {
+            Some((end, end_time_zone)) => end_time_zone.resolve(end) - time_zone.resolve(start),
+            None => return Ok(None),
+        }
+    } else if let Some(duration) = get("DURATION") {
+        parse_duration(&duration.value)?
+    } else {
+        return Ok(None);
+    };
+    let recurrence = match get("RRULE") {
+        Some(rule) => parse_recurrence(&rule.value, start, time_zone)?,
+        None => None,
+    };
+    let mut exceptions = Vec::new();
+    for exdate in properties.iter().filter(|p| p.name == "EXDATE") {
+        for value in exdate.value.split(',') {
+            if let Some(date_time) = parse_date_time(value)? {
+                exceptions.push(exdate.zone_of(value, time_zone).resolve(date_time));
+            }
+        }
+    }
+    Ok(Some(EventDefinition {
+        summary: get("SUMMARY").map(|p| p.value.clone()).unwrap_or_default(),
+        time_zone,
+        start,
+        duration,
+        recurrence,
+        exceptions,
+    }))
+}
+
+/// `time_zone` is DTSTART's, which an `UNTIL` without `Z` is in.
+fn parse_recurrence(
+    rule: &str,
+    start: NaiveDateTime,
+    time_zone: ScheduleTimeZone,
+) -> Result<Option<Recurrence>> {
+    let mut recurrence = Recurrence {
+        weekly: false,
+        interval: 1,
+        count: None,
+        until: None,
+        by_day: Day::empty(),
+    };
+    for part in rule.split(';') {
+        let Some((key, value)) = part.split_once('=') else {
+            continue;
+        };
+        match key {
+            "FREQ" => match value {
+                "DAILY" => recurrence.weekly = false,
+                "WEEKLY" => recurrence.weekly = true,
+                // Anything rarer only counts its first occurrence.
+                _ => return Ok(None),
+            },
+            "INTERVAL" => recurrence.interval = value.parse().context("invalid INTERVAL")?,
+            "COUNT" => recurrence.count = Some(value.parse().context("invalid COUNT")?),
+            "UNTIL" => {
+                let until = match parse_date_time(value)? {
+                    Some(until) => until,
+                    // A date alone takes in the whole day.
+                    None => NaiveDate::parse_from_str(value, "%Y%m%d")
+                        .with_context(|| format!("invalid UNTIL `{value}`"))?
+                        .and_hms_opt(23, 59, 59)
+                        .unwrap(),
+                };
+                recurrence.until = Some(if value.ends_with('Z') {
+                    Utc.from_utc_datetime(&until)
+                } else {
+                    time_zone.resolve(until)
+                });
+            }
+            "BYDAY" => {
+                for day in value.split(',') {
+                    // Ordinals like `1MO` only show up in monthly rules.
+                    let day = day
+                        .trim_start_matches(|c: char| c.is_ascii_digit() || c == '-' || c == '+');
+                    recurrence.by_day |= match day {
+                        "SU" => Day::SUNDAY,
+                        "MO" => Day::MONDAY,
+                        "TU" => Day::TUESDAY,
+                        "WE" => Day::WEDNESDAY,
+                        "TH" => Day::THURSDAY,
+                        "FR" => Day::FRIDAY,
+                        "SA" => Day::SATURDAY,
+                        _ => bail!("invalid BYDAY `{day}`"),
+                    };
+                }
+            }
+            _ => {}
+        }
+    }
+    if recurrence.interval == 0 {
+        bail!("invalid INTERVAL 0");
+    }
+    if recurrence.by_day.is_empty() {
+        recurrence.by_day = if recurrence.weekly {
+            Day::from_weekday(start.weekday())
+        } else {
+            Day::all()
+        };
+    }
+    Ok(Some(recurrence))
+}
+
+/// `None` for all-day dates.
+fn parse_date_time(value: &str) -> Result<Option<NaiveDateTime>> {
+    let value = value.trim_end_matches('Z');
+    if !value.contains('T') {
+        return Ok(None);
+    }
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .map(Some)
+        .with_context(|| format!("invalid date-time `{value}`"))
+}
+
+/// Parses durations like `PT1H30M` or `P1D`.
+fn parse_duration(value: &str) -> Result<ChronoDuration> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.trim_start_matches('+')),
+    };
+    let Some(value) = value.strip_prefix('P') else {
+        bail!("invalid duration `{value}`");
+    };
+    let mut total = ChronoDuration::zero();
+    let mut number = String::new();
+    for c in value.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        if c == 'T' {
+            continue;
+        }
+        let n: i64 = number
+            .parse()
+            .with_context(|| format!("invalid duration `{value}`"))?;
+        number.clear();
+        total = total
+            + match c {
+                'W' => ChronoDuration::weeks(n),
+                'D' => ChronoDuration::days(n),
+                'H' => ChronoDuration::hours(n),
+                'M' => ChronoDuration::minutes(n),
+                'S' => ChronoDuration::seconds(n),
+                _ => bail!("invalid duration `{value}`"),
+            };
+    }
+    Ok(if negative { -total } else { total })
+}
+
+#[derive(Debug, Clone)]
+struct Property {
+    name: String,
+    parameters: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Result<Property> {
+        // Parameter values may be quoted and contain `:` or `;`.
+        let mut in_quotes = false;
+        let mut value_start = None;
+        for (i, c) in line.char_indices() {
+            match c {
+                '"' => in_quotes = !in_quotes,
+                ':' if !in_quotes => {
+                    value_start = Some(i);
+                    break;
+                }
+                _ => {}
+            }
+        }
+        let Some(value_start) = value_start else {
+            bail!("invalid content line `{line}`");
+        };
+        let mut head = line[..value_start].split(';');
+        let name = head.next().unwrap_or_default().to_ascii_uppercase();
+        let parameters = head
+            .filter_map(|parameter| parameter.split_once('='))
+            .map(|(k, v)| (k.to_ascii_uppercase(), v.trim_matches('"').to_string()))
+            .collect();
+        Ok(Property {
+            name,
+            parameters,
+            value: line[value_start + 1..].to_string(),
+        })
+    }
+
+    fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The wall clock time and the zone it's in, `None` for all-day dates.
+    fn date_time(&self) -> Result<Option<(NaiveDateTime, ScheduleTimeZone)>> {
+        if self.parameter("VALUE") == Some("DATE") {
+            return Ok(None);
+        }
+        let Some(date_time) = parse_date_time(&self.value)? else {
+            return Ok(None);
+        };
+        let time_zone = self.zone_of(&self.value, ScheduleTimeZone::Local);
+        Ok(Some((date_time, time_zone)))
+    }
+
+    /// The zone `value`, one of this property's values, is in. Zones we
+    /// don't know, like Windows names, fall back to `default`.
+    fn zone_of(&self, value: &str, default: ScheduleTimeZone) -> ScheduleTimeZone {
+        if value.ends_with('Z') {
+            ScheduleTimeZone::Iana(chrono_tz::UTC)
+        } else {
+            self.parameter("TZID")
+                .and_then(|tzid| ScheduleTimeZone::parse(tzid).ok())
+                .unwrap_or(default)
+        }
+    }
+}
+
+/// Busy while an event from any of the configured `.ics` files or
+/// directories of them is going on.
+pub struct CalendarSource {
+    paths: Vec<PathBuf>,
+    definitions: Vec<EventDefinition>,
+    loaded_at: Option<DateTime<Utc>>,
+    newest_modification: Option<SystemTime>,
+    events: Vec<Event>,
+    expanded_until: Option<DateTime<Utc>>,
+}
+
+impl CalendarSource {
+    pub fn new(paths: Vec<PathBuf>) -> CalendarSource {
+        CalendarSource {
+            paths,
+            definitions: Vec::new(),
+            loaded_at: None,
+            newest_modification: None,
+            events: Vec::new(),
+            expanded_until: None,
+        }
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        fn collect(path: &Path, files: &mut Vec<PathBuf>) {
+            if path.is_dir() {
+                let Ok(entries) = fs::read_dir(path) else {
+                    return;
+                };
+                for entry in entries.flatten() {
+                    collect(&entry.path(), files);
+                }
+            } else if path
+                .extension()
+                .is_some_and(|e| e.eq_ignore_ascii_case("ics"))
+            {
+                files.push(path.to_path_buf());
+            }
+        }
+        let mut files = Vec::new();
+        for path in &self.paths {
+            collect(path, &mut files);
+        }
+        files
+    }
+
+    /// Files that fail to load are left out, and named in the error.
+    fn reload_if_changed(&mut self, now: DateTime<Utc>) -> Result<()> {
+        if self
+            .loaded_at
+            .is_some_and(|at| now - at < ChronoDuration::seconds(RELOAD_INTERVAL_SECS))
+        {
+            return Ok(());
+        }
+        self.loaded_at = Some(now);
+        let files = self.files();
+        let newest = files
+            .iter()
+            .filter_map(|f| f.metadata().and_then(|m| m.modified()).ok())
+            .max();
+        if self.newest_modification.is_some() && newest == self.newest_modification {
+            return Ok(());
+        }
+        self.newest_modification = newest;
+        self.definitions.clear();
+        self.expanded_until = None;
+        let mut skipped = Vec::new();
+        for file in files {
+            let parsed = fs::read_to_string(&file)
+                .map_err(anyhow::Error::from)
+                .and_then(|content| parse_calendar(&content));
+            match parsed {
+                Ok(definitions) => self.definitions.extend(definitions),
+                Err(e) => skipped.push(format!("{}: {e:#}", file.display())),
+            }
+        }
+        if !skipped.is_empty() {
+            bail!("skipped calendars: {}", skipped.join("; "));
+        }
+        Ok(())
+    }
+
+    /// Fails once after files that don't load were found, the rest are
+    /// used anyway.
+    pub fn events_around(&mut self, now: DateTime<Utc>) -> Result<&[Event]> {
+        self.reload_if_changed(now)?;
+        let horizon = ChronoDuration::hours(HORIZON_HOURS);
+        if self
+            .expanded_until
+            .is_none_or(|until| now + horizon / 2 > until)
+        {
+            let until = now + horizon;
+            self.events = self
+                .definitions
+                .iter()
+                .flat_map(|d| d.occurrences(now - horizon, until))
+                .collect();
+            self.events.sort_by_key(|e| e.start);
+            self.expanded_until = Some(until);
+        }
+        Ok(&self.events)
+    }
+}
+
+impl BusySource for CalendarSource {
+    fn busy_at(&mut self, now: DateTime<Utc>) -> Result<Option<Busy>> {
+        let events = self.events_around(now)?;
+        let Some(current) = events.iter().find(|e| e.start <= now && now < e.end) else {
+            return Ok(None);
+        };
+        // Back-to-back meetings count as one busy stretch.
+        let mut until = current.end;
+        for event in events {
+            if event.start <= until && event.end > until {
+                until = event.end;
+            }
+        }
+        Ok(Some(Busy {
+            reason: format!("In a meeting: {}", current.summary),
+            until: Some(until),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+SUMMARY:Standup\r
+DTSTART;TZID=Europe/Berlin:20230605T093000\r
+DURATION:PT15M\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,WE,FR\r
+EXDATE;TZID=Europe/Berlin:20230607T093000\r
+BEGIN:VALARM\r
+TRIGGER:-PT5M\r
+DURATION:PT1H\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Planning with a very long\r
+  title\r
+DTSTART:20230605T120000Z\r
+DTEND:20230605T130000Z\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Focus time\r
+TRANSP:TRANSPARENT\r
+DTSTART:20230605T140000Z\r
+DTEND:20230605T160000Z\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Holiday\r
+DTSTART;VALUE=DATE:20230606\r
+DTEND;VALUE=DATE:20230607\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn expands_recurring_events() {
+        let definitions = parse_calendar(CALENDAR).unwrap();
+        let events: Vec<_> = definitions
+            .iter()
+            .flat_map(|d| d.occurrences(utc("2023-06-05T00:00:00Z"), utc("2023-06-10T00:00:00Z")))
+            .map(|e| (e.summary, e.start, e.end))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (
+                    "Standup".to_string(),
+                    utc("2023-06-05T07:30:00Z"),
+                    utc("2023-06-05T07:45:00Z")
+                ),
+                (
+                    "Standup".to_string(),
+                    utc("2023-06-09T07:30:00Z"),
+                    utc("2023-06-09T07:45:00Z")
+                ),
+                (
+                    "Planning with a very long title".to_string(),
+                    utc("2023-06-05T12:00:00Z"),
+                    utc("2023-06-05T13:00:00Z")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn date_only_until_ends_the_series() {
+        let calendar = "BEGIN:VEVENT\r
+SUMMARY:Offsite\r
+DTSTART:20230605T120000Z\r
+DURATION:PT1H\r
+RRULE:FREQ=DAILY;UNTIL=20230607\r
+END:VEVENT\r
+";
+        let definitions = parse_calendar(calendar).unwrap();
+        let starts: Vec<_> = definitions[0]
+            .occurrences(utc("2023-06-05T00:00:00Z"), utc("2023-06-10T00:00:00Z"))
+            .into_iter()
+            .map(|e| e.start)
+            .collect();
+        assert_eq!(
+            starts,
+            vec![
+                utc("2023-06-05T12:00:00Z"),
+                utc("2023-06-06T12:00:00Z"),
+                utc("2023-06-07T12:00:00Z"),
+            ]
+        );
+        assert!(parse_calendar(&calendar.replace("20230607", "June 7")).is_err());
+    }
+
+    fn starts(calendar: &str, from: &str, to: &str) -> Vec<DateTime<Utc>> {
+        parse_calendar(calendar).unwrap()[0]
+            .occurrences(utc(from), utc(to))
+            .into_iter()
+            .map(|e| e.start)
+            .collect()
+    }
+
+    #[test]
+    fn daily_rules_filter_by_day() {
+        let calendar = "BEGIN:VEVENT\r
+DTSTART:20230605T090000Z\r
+DURATION:PT15M\r
+RRULE:FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR\r
+END:VEVENT\r
+";
+        assert_eq!(
+            starts(calendar, "2023-06-09T00:00:00Z", "2023-06-13T00:00:00Z"),
+            vec![utc("2023-06-09T09:00:00Z"), utc("2023-06-12T09:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn exdates_in_their_own_zone() {
+        let calendar = "BEGIN:VEVENT\r
+DTSTART;TZID=Europe/Berlin:20230605T093000\r
+DURATION:PT15M\r
+RRULE:FREQ=DAILY;COUNT=3\r
+EXDATE:20230606T073000Z\r
+END:VEVENT\r
+";
+        assert_eq!(
+            starts(calendar, "2023-06-05T00:00:00Z", "2023-06-10T00:00:00Z"),
+            vec![utc("2023-06-05T07:30:00Z"), utc("2023-06-07T07:30:00Z")]
+        );
+    }
+
+    #[test]
+    fn date_only_until_in_the_event_zone() {
+        // Auckland is twelve hours ahead, its 7 June starts on the 6th in UTC.
+        let calendar = "BEGIN:VEVENT\r
+DTSTART;TZID=Pacific/Auckland:20230605T080000\r
+DURATION:PT15M\r
+RRULE:FREQ=DAILY;UNTIL=20230606\r
+END:VEVENT\r
+";
+        assert_eq!(
+            starts(calendar, "2023-06-01T00:00:00Z", "2023-06-10T00:00:00Z"),
+            vec![utc("2023-06-04T20:00:00Z"), utc("2023-06-05T20:00:00Z")]
+        );
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(
+            parse_duration("PT1H30M").unwrap(),
+            ChronoDuration::minutes(90)
+        );
+        assert_eq!(
+            parse_duration("P1DT2S").unwrap(),
+            ChronoDuration::seconds(86402)
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    config::{ActivityConfig, Config},
+    safety,
+    scoring::ScoringProfile,
+    time::FormattedDuration,
+    trace::{self, TraceFrame},
+};
+
+/// The same window `ActivityBreak` averages its activity level over.
+const LEVEL_WINDOW_SECS: usize = 60;
+
+/// Gaps shorter than this are just typing rhythm, not pauses.
+const MIN_PAUSE: Duration = Duration::from_secs(10);
+
+const PERCENTILES: [f64; 5] = [0.1, 0.25, 0.5, 0.75, 0.9];
+
+/// The activity level at every second of a trace, as `ActivityBreak` would
+/// see it, skipping the first minute while the window fills.
+pub fn activity_levels(frames: &[TraceFrame], profile: &ScoringProfile) -> Vec<f64> {
+    let Some(last) = frames.last() else {
+        return Vec::new();
+    };
+    let mut per_second = vec![0.0; last.offset.as_secs() as usize + 1];
+    for frame in frames {
+        per_second[frame.offset.as_secs() as usize] += frame.score(profile);
+    }
+
+    let mut levels = Vec::with_capacity(per_second.len());
+    let mut window_sum = 0.0;
+    for (second, score) in per_second.iter().enumerate() {
+        window_sum += score;
+        if second >= LEVEL_WINDOW_SECS {
+            window_sum -= per_second[second - LEVEL_WINDOW_SECS];
+        }
+        if second + 1 >= LEVEL_WINDOW_SECS.min(per_second.len()) {
+            levels.push(window_sum / LEVEL_WINDOW_SECS as f64);
+        }
+    }
+    levels
+}
+
+/// Stretches without any input, at least [`MIN_PAUSE`] long.
+pub fn pauses(frames: &[TraceFrame]) -> Vec<Duration> {
+    frames
+        .windows(2)
+        .map(|pair| {
+            let start = pair[1].offset.saturating_sub(pair[1].delta);
+            start.saturating_sub(pair[0].offset)
+        })
+        .filter(|pause| *pause >= MIN_PAUSE)
+        .collect()
+}
+
+/// Linear interpolation between the closest ranks. `sorted` must be sorted
+/// and not empty.
+pub fn percentile<T>(sorted: &[T], p: f64) -> f64
+where
+    T: Copy + Into<f64>,
+{
+    let rank = p.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let (low, high) = (rank.floor() as usize, rank.ceil() as usize);
+    let (low_value, high_value) = (sorted[low].into(), sorted[high].into());
+    low_value + (high_value - low_value) * (rank - low as f64)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub high_activity_level: f64,
+    pub low_activity_level: f64,
+    pub idle_secs: u64,
+}
+
+impl Suggestion {
+    /// High activity is the busiest quarter of the time spent working and
+    /// low is the quietest tenth. Idle needs to outlast nine in ten of the
+    /// pauses taken while working, so only real breaks count.
+    pub fn from_samples(levels: &[f64], pauses: &[Duration]) -> Option<Suggestion> {
+        let mut working: Vec<f64> = levels.iter().copied().filter(|l| *l > 0.0).collect();
+        if working.is_empty() {
+            return None;
+        }
+        working.sort_by(f64::total_cmp);
+        let high_activity_level = percentile(&working, 0.75);
+        let low_activity_level = percentile(&working, 0.1).min(high_activity_level / 2.0);
+
+        let mut pause_secs: Vec<u32> = pauses.iter().map(|p| p.as_secs() as u32).collect();
+        pause_secs.sort();
+        let idle_secs = if pause_secs.is_empty() {
+            ActivityConfig::default().idle_secs
+        } else {
+            // Whole minutes, between two and fifteen.
+            let secs = percentile(&pause_secs, 0.9) as u64;
+            (secs.div_ceil(60) * 60).clamp(2 * 60, 15 * 60)
+        };
+        Some(Suggestion {
+            high_activity_level: round(high_activity_level),
+            low_activity_level: round(low_activity_level),
+            idle_secs,
+        })
+    }
+
+    pub fn write_to_config(&self) -> Result<()> {
+        let path = Config::edit(|document| {
+            let activity = document.entry("activity").or_insert_with(toml_edit::table);
+            if let Some(activity) = activity.as_table_like_mut() {
+                activity.insert(
+                    "high_activity_level",
+                    toml_edit::value(self.high_activity_level),
+                );
+                activity.insert(
+                    "low_activity_level",
+                    toml_edit::value(self.low_activity_level),
+                );
+                activity.insert("idle_secs", toml_edit::value(self.idle_secs as i64));
+            }
+        })?;
+        println!("Wrote the suggestion to {}.", path.display());
+        Ok(())
+    }
+}
+
+fn round(level: f64) -> f64 {
+    (level * 100.0).round() / 100.0
+}
+
+/// Samples for `duration`, or reads `trace` instead, then prints the activity
+/// statistics and a suggestion. Ctrl-C stops sampling early and the
+/// suggestion is made from what was sampled until then.
+pub fn run(
+    config: &ActivityConfig,
+    duration: Duration,
+    trace: Option<&Path>,
+    write: bool,
+) -> Result<()> {
+    let frames = match trace {
+        Some(path) => trace::read_trace(path)?,
+        None => {
+            safety::install(None)?;
+            println!(
+                "Sampling activity for {}. Keep working as usual, or press Ctrl-C to stop early.",
+                FormattedDuration::new(duration)
+            );
+            let mut frames = Vec::new();
+            trace::sample(config, Some(duration), |frame| {
+                frames.push(frame);
+                Ok(())
+            })?;
+            if safety::exit_requested() {
+                println!("Stopped early, calibrating with what was sampled so far.");
+            }
+            frames
+        }
+    };
+    let profile = config.scoring_profile()?;
+    let levels = activity_levels(&frames, &profile);
+    let pauses = pauses(&frames);
+    let Some(suggestion) = Suggestion::from_samples(&levels, &pauses) else {
+        bail!("no activity was recorded, nothing to calibrate against");
+    };
+
+    let mut sorted = levels.clone();
+    sorted.sort_by(f64::total_cmp);
+    println!(
+        "Activity level over {} with the `{}` profile:",
+        FormattedDuration::new(Duration::from_secs(levels.len() as u64)),
+        config.profile
+    );
+    for p in PERCENTILES {
+        println!("  p{:<3} {:.2}", (p * 100.0) as u32, percentile(&sorted, p));
+    }
+    println!(
+        "Pauses of {}s or more: {}",
+        MIN_PAUSE.as_secs(),
+        pauses.len()
+    );
+
+    println!();
+    println!("Suggested settings:");
+    println!("  [activity]");
+    println!("  high_activity_level = {}", suggestion.high_activity_level);
+    println!("  low_activity_level = {}", suggestion.low_activity_level);
+    println!("  idle_secs = {}", suggestion.idle_secs);
+
+    if write {
+        suggestion.write_to_config()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::activity_monitor::{ActivityKind, KeyClass};
+
+    #[test]
+    fn suggests_from_trace() {
+        assert_eq!(percentile(&[1.0, 2.0, 3.0, 4.0, 5.0], 0.5), 3.0);
+        assert_eq!(percentile(&[1.0, 2.0], 0.25), 1.25);
+
+        // Typing in bursts of three minutes with pauses of a minute and a
+        // half, one key a second.
+        let frames: Vec<_> = (0..20u64)
+            .flat_map(|burst| {
+                (0..180).map(move |s| TraceFrame {
+                    offset: Duration::from_secs(burst * 270 + s + 1),
+                    delta: Duration::from_secs(1),
+                    interval: Duration::from_secs(1),
+                    activities: vec![(
+                        ActivityKind::KeyJustPress {
+                            class: KeyClass::Typing,
+                        },
+                        1,
+                        1,
+                    )],
+                })
+            })
+            .collect();
+        let levels = activity_levels(&frames, &ScoringProfile::default());
+        let pauses = pauses(&frames);
+        assert_eq!(pauses.len(), 19);
+
+        let suggestion = Suggestion::from_samples(&levels, &pauses).unwrap();
+        // 75 a second, all the time while typing.
+        assert_eq!(suggestion.high_activity_level, 75.0);
+        assert!(suggestion.low_activity_level < suggestion.high_activity_level);
+        assert_eq!(suggestion.idle_secs, 2 * 60);
+    }
+}
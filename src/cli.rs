@@ -0,0 +1,73 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "pomodoro-ss",
+    about = "Pomodoro timer that makes you take your breaks"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Inspect the configured schedule
+    Schedule {
+        #[command(subcommand)]
+        command: ScheduleCommand,
+    },
+    /// Record which kinds of input happen, never which keys, to a trace file
+    RecordActivity {
+        path: PathBuf,
+        /// Stop after this many seconds instead of on Ctrl-C
+        #[arg(long)]
+        secs: Option<u64>,
+    },
+    /// Replay a recorded trace and print when activity breaks would fire
+    ReplayActivity {
+        path: PathBuf,
+        /// Scoring profile to use instead of the configured one
+        #[arg(long)]
+        profile: Option<String>,
+        #[arg(long)]
+        high_activity_level: Option<f64>,
+        #[arg(long)]
+        low_activity_level: Option<f64>,
+        #[arg(long)]
+        active_secs: Option<u64>,
+        #[arg(long)]
+        idle_secs: Option<u64>,
+    },
+    /// Sample activity during normal work and suggest activity break settings
+    Calibrate {
+        /// How long to sample for
+        #[arg(long, default_value_t = 30)]
+        minutes: u64,
+        /// Use a recorded trace instead of sampling
+        #[arg(long)]
+        trace: Option<PathBuf>,
+        /// Write the suggested settings to the config file
+        #[arg(long)]
+        write: bool,
+    },
+    /// Restart the app if its heartbeats on stdin stop during a break. The
+    /// app starts this itself when the watchdog is enabled
+    #[command(hide = true)]
+    Watchdog {
+        #[arg(long, default_value_t = 10)]
+        timeout_secs: u64,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ScheduleCommand {
+    /// Print when work windows open and breaks would fire
+    Preview {
+        /// How many days to simulate, starting now
+        #[arg(long, default_value_t = 7)]
+        days: u32,
+    },
+}
@@ -0,0 +1,452 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{activity_monitor::DEFAULT_SAMPLE_RATE_HZ, scoring::ScoringProfile};
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub schedule: ScheduleConfig,
+    pub breaks: BreakConfig,
+    pub deferral: DeferralConfig,
+    pub calendar: CalendarConfig,
+    pub presentation: PresentationConfig,
+    pub media: MediaConfig,
+    pub activity: ActivityConfig,
+    pub blocking: BlockingConfig,
+    pub lock: LockConfig,
+    pub watchdog: WatchdogConfig,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    /// An IANA name such as `Europe/Berlin`, or `local` for the system zone.
+    pub time_zone: Option<String>,
+    /// Breaks only run inside these windows. No windows means always.
+    pub windows: Vec<WindowConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowConfig {
+    /// Day names like `mon`, or `weekdays`, `weekend` and `all`.
+    pub days: Vec<String>,
+    /// `HH:MM` in the schedule's time zone.
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BreakConfig {
+    pub work_secs: u64,
+    pub break_secs: u64,
+    pub long_break_secs: u64,
+    /// Every n-th break is a long one. `0` disables long breaks.
+    pub long_break_every: u32,
+    /// Which remembered answer to how breaks are enforced, and which
+    /// `lock.profiles` entry, applies. Set it to keep that apart from
+    /// `activity.profile`, which it defaults to.
+    pub profile: Option<String>,
+}
+
+impl Default for BreakConfig {
+    fn default() -> Self {
+        BreakConfig {
+            work_secs: 25 * 60,
+            break_secs: 5 * 60,
+            long_break_secs: 15 * 60,
+            long_break_every: 4,
+            profile: None,
+        }
+    }
+}
+
+impl BreakConfig {
+    pub fn work_duration(&self) -> Duration {
+        Duration::from_secs(self.work_secs)
+    }
+
+    pub fn break_duration(&self) -> Duration {
+        Duration::from_secs(self.break_secs)
+    }
+
+    pub fn long_break_duration(&self) -> Duration {
+        Duration::from_secs(self.long_break_secs)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeferralConfig {
+    /// A due break is forced after being postponed this long.
+    pub max_secs: u64,
+    /// How long after a busy period ends the break is forced.
+    pub after_busy_secs: u64,
+}
+
+impl Default for DeferralConfig {
+    fn default() -> Self {
+        DeferralConfig {
+            max_secs: 60 * 60,
+            after_busy_secs: 2 * 60,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CalendarConfig {
+    /// `.ics` files, or directories of them such as those kept in sync by a
+    /// calendar client. A leading `~/` means the home directory.
+    pub paths: Vec<PathBuf>,
+}
+
+impl CalendarConfig {
+    pub fn expanded_paths(&self) -> Vec<PathBuf> {
+        self.paths.iter().map(|path| expand_home(path)).collect()
+    }
+}
+
+pub fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PresentationConfig {
+    /// Postpone breaks while the active window is fullscreen. X11 only.
+    pub detect_fullscreen: bool,
+    /// `WM_CLASS` names of windows that also postpone breaks while active,
+    /// matched case-insensitively against either the instance or the class.
+    pub window_classes: Vec<String>,
+}
+
+impl Default for PresentationConfig {
+    fn default() -> Self {
+        PresentationConfig {
+            detect_fullscreen: true,
+            window_classes: Vec::new(),
+        }
+    }
+}
+
+impl PresentationConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.detect_fullscreen || !self.window_classes.is_empty()
+    }
+}
+
+/// MPRIS media players on the session bus. Linux only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MediaConfig {
+    /// Postpone breaks while anything plays with a fullscreen window in front.
+    pub defer_while_fullscreen_video: bool,
+    /// Players that postpone breaks whenever they play, like `vlc`.
+    pub players: Vec<String>,
+    /// Pause whatever is playing when a break starts and resume it after.
+    pub pause_on_break: bool,
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        MediaConfig {
+            defer_while_fullscreen_video: true,
+            players: Vec::new(),
+            pause_on_break: false,
+        }
+    }
+}
+
+impl MediaConfig {
+    pub fn defers(&self) -> bool {
+        self.defer_while_fullscreen_video || !self.players.is_empty()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.defers() || self.pause_on_break
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActivityConfig {
+    pub input_backend: InputBackend,
+    /// How many times a second input is sampled.
+    pub sample_rate_hz: u32,
+    /// How input is scored. One of `profiles`, or the built-in `default`,
+    /// `typing`, `mouse` and `gaming`.
+    pub profile: String,
+    pub profiles: BTreeMap<String, ScoringProfile>,
+    /// Activity per second, averaged over a minute, that counts as working
+    /// hard.
+    pub high_activity_level: f64,
+    /// Below this the time counts as idle.
+    pub low_activity_level: f64,
+    /// A break is due after this long at a high activity level.
+    pub active_secs: u64,
+    /// This long idle counts as a break already taken.
+    pub idle_secs: u64,
+    pub break_secs: u64,
+}
+
+impl Default for ActivityConfig {
+    fn default() -> Self {
+        ActivityConfig {
+            input_backend: InputBackend::default(),
+            sample_rate_hz: DEFAULT_SAMPLE_RATE_HZ,
+            profile: "default".to_string(),
+            profiles: BTreeMap::new(),
+            high_activity_level: 2.0,
+            low_activity_level: 0.2,
+            active_secs: 50 * 60,
+            idle_secs: 5 * 60,
+            break_secs: 5 * 60,
+        }
+    }
+}
+
+impl ActivityConfig {
+    pub fn scoring_profile(&self) -> Result<ScoringProfile> {
+        ScoringProfile::find(&self.profile, &self.profiles)
+    }
+
+    pub fn active_duration(&self) -> Duration {
+        Duration::from_secs(self.active_secs)
+    }
+
+    pub fn idle_duration(&self) -> Duration {
+        Duration::from_secs(self.idle_secs)
+    }
+
+    pub fn break_duration(&self) -> Duration {
+        Duration::from_secs(self.break_secs)
+    }
+}
+
+/// Input blocked during breaks. Linux only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlockingConfig {
+    /// Keys that keep working during a break, as evdev names like
+    /// `KEY_VOLUMEUP`. They're re-emitted through `/dev/uinput`.
+    pub passthrough_keys: Vec<String>,
+    pub emergency: EmergencyConfig,
+}
+
+impl Default for BlockingConfig {
+    fn default() -> Self {
+        let keys = [
+            "KEY_PLAYPAUSE",
+            "KEY_NEXTSONG",
+            "KEY_PREVIOUSSONG",
+            "KEY_STOPCD",
+            "KEY_MUTE",
+            "KEY_VOLUMEUP",
+            "KEY_VOLUMEDOWN",
+        ];
+        BlockingConfig {
+            passthrough_keys: keys.iter().map(|key| key.to_string()).collect(),
+            emergency: EmergencyConfig::default(),
+        }
+    }
+}
+
+/// Ending a break with blocked input early.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmergencyConfig {
+    /// evdev key names held down together to end the break. Empty disables
+    /// the override.
+    pub chord: Vec<String>,
+    pub hold_secs: u64,
+    /// Overrides allowed a day. Unlimited if not set.
+    pub daily_budget: Option<u32>,
+}
+
+impl Default for EmergencyConfig {
+    fn default() -> Self {
+        EmergencyConfig {
+            chord: vec![
+                "KEY_LEFTCTRL".to_string(),
+                "KEY_LEFTALT".to_string(),
+                "KEY_ESC".to_string(),
+            ],
+            hold_secs: 5,
+            daily_budget: None,
+        }
+    }
+}
+
+impl EmergencyConfig {
+    pub fn hold_duration(&self) -> Duration {
+        Duration::from_secs(self.hold_secs)
+    }
+}
+
+/// A separate process that restarts the app if it stops responding while
+/// enforcing a break. Linux only.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    pub enabled: bool,
+    /// How long without a heartbeat counts as not responding.
+    pub timeout_secs: u64,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            enabled: false,
+            timeout_secs: 10,
+        }
+    }
+}
+
+impl WatchdogConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
+
+/// The lock session break mode.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LockConfig {
+    pub early_unlock: EarlyUnlock,
+    /// Overrides `early_unlock` by `breaks.profile`.
+    pub profiles: BTreeMap<String, EarlyUnlock>,
+}
+
+impl LockConfig {
+    pub fn early_unlock_for(&self, profile: &str) -> EarlyUnlock {
+        self.profiles
+            .get(profile)
+            .copied()
+            .unwrap_or(self.early_unlock)
+    }
+}
+
+/// What happens when the session is unlocked before the break is over.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EarlyUnlock {
+    #[default]
+    Relock,
+    /// Ends the break and notes it in the journal.
+    CutShort,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputBackend {
+    /// evdev under Wayland, `device_query` otherwise.
+    #[default]
+    Auto,
+    DeviceQuery,
+    /// Reads `/dev/input` directly. Linux only.
+    Evdev,
+}
+
+impl Config {
+    /// The profile breaks are enforced by, see [`BreakConfig::profile`].
+    pub fn break_profile(&self) -> &str {
+        self.breaks
+            .profile
+            .as_deref()
+            .unwrap_or(&self.activity.profile)
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("pomodoro-ss").join("config.toml"))
+    }
+
+    /// Loads the config file, or the defaults if there isn't one.
+    pub fn load() -> Result<Config> {
+        let Some(path) = Config::path() else {
+            return Ok(Config::default());
+        };
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    /// Applies `edit` to the config file as it is on disk, creating it if
+    /// needed, so settings that were never written stay at their defaults.
+    /// Comments and formatting are kept.
+    pub fn edit<F>(edit: F) -> Result<PathBuf>
+    where
+        F: FnOnce(&mut toml_edit::Document),
+    {
+        let path = Config::path().context("no config directory on this system")?;
+        let content = if path.exists() {
+            fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?
+        } else {
+            String::new()
+        };
+        let content = edit_content(&content, edit)
+            .with_context(|| format!("failed to edit {}", path.display()))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&path, content).with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+fn edit_content<F>(content: &str, edit: F) -> Result<String>
+where
+    F: FnOnce(&mut toml_edit::Document),
+{
+    let mut document = content.parse::<toml_edit::Document>()?;
+    edit(&mut document);
+    let content = document.to_string();
+    // Catch edits that would leave the file unloadable.
+    toml::from_str::<Config>(&content).context("refusing to write an invalid config")?;
+    Ok(content)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn edits_keep_comments() {
+        let content = "# Tuned by hand.\n[activity]\nprofile = \"typing\" # for now\n";
+        let edited = edit_content(content, |document| {
+            document["activity"]["idle_secs"] = toml_edit::value(300);
+        })
+        .unwrap();
+        assert_eq!(
+            edited,
+            "# Tuned by hand.\n[activity]\nprofile = \"typing\" # for now\nidle_secs = 300\n"
+        );
+        assert!(edit_content(content, |document| {
+            document["activity"]["idle_secs"] = toml_edit::value("long");
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn break_profile_defaults_to_activity_profile() {
+        let mut config: Config = toml::from_str("[activity]\nprofile = \"typing\"\n").unwrap();
+        assert_eq!(config.break_profile(), "typing");
+        config.breaks.profile = Some("focus".to_string());
+        assert_eq!(config.break_profile(), "focus");
+    }
+}
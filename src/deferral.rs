@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::config::DeferralConfig;
+
+/// Why the user shouldn't be interrupted right now.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Busy {
+    pub reason: String,
+    /// When the busy period is expected to end, if known.
+    pub until: Option<DateTime<Utc>>,
+}
+
+pub trait BusySource {
+    fn busy_at(&mut self, now: DateTime<Utc>) -> Result<Option<Busy>>;
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeferDecision {
+    Proceed,
+    /// The break was just postponed for the first time.
+    Deferred(Busy),
+    Hold,
+}
+
+/// How long a busy source with no end in sight, or one that failed, isn't
+/// asked again.
+const RECHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Postpones a due break while any source reports the user as busy, but never
+/// for longer than `max_deferral`.
+pub struct BreakDeferral {
+    sources: Vec<Box<dyn BusySource>>,
+    max_deferral: Duration,
+    after_busy: Duration,
+    deferred_since: Option<DateTime<Utc>>,
+    hold_until: Option<DateTime<Utc>>,
+}
+
+impl BreakDeferral {
+    pub fn new(max_deferral: Duration, after_busy: Duration) -> BreakDeferral {
+        BreakDeferral {
+            sources: Vec::new(),
+            max_deferral,
+            after_busy,
+            deferred_since: None,
+            hold_until: None,
+        }
+    }
+
+    pub fn from_config(config: &DeferralConfig) -> BreakDeferral {
+        BreakDeferral::new(
+            Duration::from_secs(config.max_secs),
+            Duration::from_secs(config.after_busy_secs),
+        )
+    }
+
+    pub fn add_source<S>(&mut self, source: S)
+    where
+        S: BusySource + 'static,
+    {
+        self.sources.push(Box::new(source));
+    }
+
+    pub fn is_deferring(&self) -> bool {
+        self.deferred_since.is_some()
+    }
+
+    /// Called while a break is due. Fails if any source does, which holds
+    /// the break until the sources are asked again.
+    pub fn update(&mut self, now: DateTime<Utc>) -> Result<DeferDecision> {
+        if let Some(since) = self.deferred_since {
+            let deferred_for = (now - since).to_std().unwrap_or(Duration::ZERO);
+            if deferred_for >= self.max_deferral {
+                return Ok(DeferDecision::Proceed);
+            }
+        }
+        if matches!(self.hold_until, Some(until) if now < until) {
+            return Ok(DeferDecision::Hold);
+        }
+
+        // Sources can be slow to ask, and the main loop runs every frame.
+        let recheck_at = now + chrono::Duration::from_std(RECHECK_INTERVAL).unwrap();
+        let mut busy = None;
+        for source in &mut self.sources {
+            busy = source
+                .busy_at(now)
+                .inspect_err(|_| self.hold_until = Some(recheck_at))?;
+            if busy.is_some() {
+                break;
+            }
+        }
+        let Some(busy) = busy else {
+            self.hold_until = None;
+            return Ok(DeferDecision::Proceed);
+        };
+        // Give the user a moment after the busy period ends before forcing it.
+        let after_busy =
+            chrono::Duration::from_std(self.after_busy).unwrap_or(chrono::Duration::zero());
+        self.hold_until = Some(busy.until.map_or(recheck_at, |until| until + after_busy));
+        if self.deferred_since.is_none() {
+            self.deferred_since = Some(now);
+            Ok(DeferDecision::Deferred(busy))
+        } else {
+            Ok(DeferDecision::Hold)
+        }
+    }
+
+    /// Forgets the current deferral, call once the break has started.
+    pub fn reset(&mut self) {
+        self.deferred_since = None;
+        self.hold_until = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct BusyBetween(DateTime<Utc>, DateTime<Utc>);
+
+    impl BusySource for BusyBetween {
+        fn busy_at(&mut self, now: DateTime<Utc>) -> Result<Option<Busy>> {
+            Ok((self.0 <= now && now < self.1).then(|| Busy {
+                reason: "meeting".to_string(),
+                until: Some(self.1),
+            }))
+        }
+    }
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2023-06-05T09:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+            + chrono::Duration::minutes(minutes)
+    }
+
+    #[test]
+    fn holds_until_shortly_after_busy_ends() {
+        let mut deferral =
+            BreakDeferral::new(Duration::from_secs(60 * 60), Duration::from_secs(2 * 60));
+        deferral.add_source(BusyBetween(at(0), at(30)));
+
+        assert!(matches!(
+            deferral.update(at(10)).unwrap(),
+            DeferDecision::Deferred(_)
+        ));
+        assert_eq!(deferral.update(at(30)).unwrap(), DeferDecision::Hold);
+        assert_eq!(deferral.update(at(32)).unwrap(), DeferDecision::Proceed);
+    }
+
+    #[test]
+    fn never_defers_past_maximum() {
+        let mut deferral =
+            BreakDeferral::new(Duration::from_secs(20 * 60), Duration::from_secs(2 * 60));
+        deferral.add_source(BusyBetween(at(0), at(90)));
+
+        assert!(matches!(
+            deferral.update(at(0)).unwrap(),
+            DeferDecision::Deferred(_)
+        ));
+        assert_eq!(deferral.update(at(19)).unwrap(), DeferDecision::Hold);
+        assert_eq!(deferral.update(at(20)).unwrap(), DeferDecision::Proceed);
+    }
+
+    /// Busy with no known end, or failing, and counting how often it's asked.
+    struct Asked(std::rc::Rc<std::cell::Cell<usize>>, bool);
+
+    impl BusySource for Asked {
+        fn busy_at(&mut self, _now: DateTime<Utc>) -> Result<Option<Busy>> {
+            self.0.set(self.0.get() + 1);
+            if self.1 {
+                anyhow::bail!("no answer");
+            }
+            Ok(Some(Busy {
+                reason: "presenting".to_string(),
+                until: None,
+            }))
+        }
+    }
+
+    #[test]
+    fn open_ended_and_failing_sources_are_not_asked_every_frame() {
+        let secs = |secs| at(0) + chrono::Duration::seconds(secs);
+        for failing in [false, true] {
+            let asked = std::rc::Rc::default();
+            let mut deferral =
+                BreakDeferral::new(Duration::from_secs(60 * 60), Duration::from_secs(2 * 60));
+            deferral.add_source(Asked(std::rc::Rc::clone(&asked), failing));
+
+            assert_eq!(deferral.update(secs(0)).is_err(), failing);
+            assert_eq!(deferral.update(secs(5)).unwrap(), DeferDecision::Hold);
+            assert_eq!(asked.get(), 1);
+            assert_eq!(deferral.update(secs(10)).is_err(), failing);
+            assert_eq!(asked.get(), 2);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! Getting out of a break with blocked input, for when something can't wait.
+//! Every override is noted in the journal with a reason, and can count
+//! against a daily budget.
+
+use std::time::{Duration, Instant};
+
+use chrono::{Local, NaiveDate};
+use dialog::DialogBox;
+
+use crate::{
+    config::EmergencyConfig,
+    journal::{Journal, JournalEntry, JournalEvent},
+};
+
+/// Overrides recorded on `day`, local time.
+pub fn overrides_on(entries: &[JournalEntry], day: NaiveDate) -> usize {
+    entries
+        .iter()
+        .filter(|entry| matches!(entry.event, JournalEvent::EmergencyOverride { .. }))
+        .filter(|entry| entry.at.with_timezone(&Local).date_naive() == day)
+        .count()
+}
+
+/// Whether today's budget has room for another override. A journal that
+/// can't be read doesn't stand in the way.
+pub fn override_allowed(config: &EmergencyConfig, journal: Option<&Journal>) -> bool {
+    let (Some(budget), Some(journal)) = (config.daily_budget, journal) else {
+        return true;
+    };
+    match journal.entries() {
+        Ok(entries) => overrides_on(&entries, Local::now().date_naive()) < budget as usize,
+        Err(e) => {
+            eprintln!("{e:#}");
+            true
+        }
+    }
+}
+
+/// Asks why the break was overridden, on the terminal if dialogs don't work.
+pub fn ask_reason() -> String {
+    let input = dialog::Input::new("The break was overridden. What couldn't wait?")
+        .title("Emergency override")
+        .show()
+        .or_else(|_| {
+            dialog::Input::new("The break was overridden. What couldn't wait?")
+                .show_with(Box::new(dialog::backends::Stdio::new()))
+        });
+    match input {
+        Ok(Some(reason)) if !reason.trim().is_empty() => reason.trim().to_string(),
+        _ => "no reason given".to_string(),
+    }
+}
+
+/// Keys that end the break when held down together long enough.
+#[cfg(target_os = "linux")]
+pub struct KeyChord {
+    keys: Vec<evdev::Key>,
+    hold: Duration,
+    pressed: Vec<evdev::Key>,
+    held_since: Option<Instant>,
+}
+
+#[cfg(target_os = "linux")]
+impl KeyChord {
+    pub fn new(keys: Vec<evdev::Key>, hold: Duration) -> KeyChord {
+        KeyChord {
+            keys,
+            hold,
+            pressed: Vec::new(),
+            held_since: None,
+        }
+    }
+
+    /// `None` if the config has no keys in the chord.
+    pub fn from_config(config: &EmergencyConfig) -> anyhow::Result<Option<KeyChord>> {
+        let keys: Vec<_> = config
+            .chord
+            .iter()
+            .map(|name| crate::evdev_grab::parse_key(name))
+            .collect::<anyhow::Result<_>>()?;
+        Ok((!keys.is_empty()).then(|| KeyChord::new(keys, config.hold_duration())))
+    }
+
+    /// Whether the chord has been held long enough by `now`.
+    pub fn feed(&mut self, events: &[evdev::InputEvent], now: Instant) -> bool {
+        for event in events {
+            if event.event_type() != evdev::EventType::KEY {
+                continue;
+            }
+            let key = evdev::Key::new(event.code());
+            match event.value() {
+                0 => self.pressed.retain(|pressed| *pressed != key),
+                // 2 is a repeat of a held key.
+                1 if !self.pressed.contains(&key) => self.pressed.push(key),
+                _ => {}
+            }
+        }
+        if self.keys.iter().all(|key| self.pressed.contains(key)) {
+            let held_since = *self.held_since.get_or_insert(now);
+            now.duration_since(held_since) >= self.hold
+        } else {
+            self.held_since = None;
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Utc;
+
+    #[test]
+    fn budget_counts_today() {
+        let today = Local::now();
+        let entry = |at: chrono::DateTime<Local>| JournalEntry {
+            at: at.with_timezone(&Utc),
+            event: JournalEvent::EmergencyOverride {
+                reason: "deploy".to_string(),
+            },
+        };
+        let entries = [
+            entry(today),
+            entry(today - chrono::Duration::days(1)),
+            JournalEntry {
+                at: Utc::now(),
+                event: JournalEvent::BreakCutShort {
+                    after: Duration::from_secs(5),
+                },
+            },
+        ];
+        assert_eq!(overrides_on(&entries, today.date_naive()), 1);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn chord_needs_holding() {
+        use evdev::{EventType, InputEvent, Key};
+
+        let key = |key: Key, value| InputEvent::new(EventType::KEY, key.code(), value);
+        let mut chord = KeyChord::new(
+            vec![Key::KEY_LEFTCTRL, Key::KEY_ESC],
+            Duration::from_secs(3),
+        );
+        let start = Instant::now();
+        assert!(!chord.feed(&[key(Key::KEY_LEFTCTRL, 1)], start));
+        assert!(!chord.feed(&[key(Key::KEY_ESC, 1)], start));
+        assert!(!chord.feed(&[key(Key::KEY_ESC, 2)], start + Duration::from_secs(2)));
+        // Letting go starts over.
+        assert!(!chord.feed(&[key(Key::KEY_ESC, 0)], start + Duration::from_secs(2)));
+        assert!(!chord.feed(&[key(Key::KEY_ESC, 1)], start + Duration::from_secs(3)));
+        assert!(!chord.feed(&[], start + Duration::from_secs(5)));
+        assert!(chord.feed(&[], start + Duration::from_secs(6)));
+    }
+}
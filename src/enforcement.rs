@@ -0,0 +1,581 @@
+//! What happens to the computer while a break runs.
+//!
+//! On Linux locking, suspending, hibernating and shutting down all go
+//! through systemd-logind, via `loginctl` and `systemctl`.
+
+use std::{
+    cell::RefCell,
+    process,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use dialog::{backends::Backend, Choice, DialogBox};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Config, EarlyUnlock},
+    emergency,
+    input_block::InputBlocker,
+    journal::{Journal, JournalEvent},
+    state::State,
+};
+
+/// Runs external programs, so actions can be tested without suspending the
+/// machine running the tests.
+pub trait CommandRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<()>;
+    /// What the program printed.
+    fn output(&self, program: &str, args: &[&str]) -> Result<String>;
+}
+
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<()> {
+        let status = process::Command::new(program)
+            .args(args)
+            .status()
+            .with_context(|| format!("failed to run `{program}`"))?;
+        if !status.success() {
+            bail!("`{program} {}` failed: {status}", args.join(" "));
+        }
+        Ok(())
+    }
+
+    fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        let output = process::Command::new(program)
+            .args(args)
+            .output()
+            .with_context(|| format!("failed to run `{program}`"))?;
+        if !output.status.success() {
+            bail!("`{program} {}` failed: {}", args.join(" "), output.status);
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Records the commands it's asked to run instead of running them, and
+/// answers all of them with `output`. Clones share both.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockRunner {
+    pub commands: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    pub output: std::sync::Arc<std::sync::Mutex<String>>,
+    pub fail: bool,
+}
+
+#[cfg(test)]
+impl MockRunner {
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.lock().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+impl CommandRunner for MockRunner {
+    fn run(&self, program: &str, args: &[&str]) -> Result<()> {
+        let mut command = vec![program];
+        command.extend(args);
+        self.commands.lock().unwrap().push(command.join(" "));
+        if self.fail {
+            bail!("`{program}` failed");
+        }
+        Ok(())
+    }
+
+    fn output(&self, program: &str, args: &[&str]) -> Result<String> {
+        self.run(program, args)?;
+        Ok(self.output.lock().unwrap().clone())
+    }
+}
+
+/// Started when a break starts, updated every frame while it runs and ended
+/// when it's over. Dropping without ending must not leave the computer
+/// unusable.
+pub trait EnforcementAction {
+    fn name(&self) -> &'static str;
+    fn start(&mut self) -> Result<()>;
+    fn update(&mut self) -> Result<()> {
+        Ok(())
+    }
+    fn end(&mut self) -> Result<()> {
+        Ok(())
+    }
+    /// The user ended the break early and the action let them.
+    fn is_cut_short(&self) -> bool {
+        false
+    }
+    /// The user ended the break with the emergency override.
+    fn is_overridden(&self) -> bool {
+        false
+    }
+}
+
+/// Borrows the app's input blocker for the break, so its passthrough device
+/// outlives the break.
+pub struct BlockInput {
+    input_blocker: Rc<RefCell<InputBlocker>>,
+}
+
+impl BlockInput {
+    pub fn new(input_blocker: Rc<RefCell<InputBlocker>>) -> BlockInput {
+        BlockInput { input_blocker }
+    }
+}
+
+impl EnforcementAction for BlockInput {
+    fn name(&self) -> &'static str {
+        "block input"
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.input_blocker.borrow_mut().set_blocked(true)
+    }
+
+    fn update(&mut self) -> Result<()> {
+        self.input_blocker.borrow_mut().set_blocked(true)
+    }
+
+    fn end(&mut self) -> Result<()> {
+        self.input_blocker.borrow_mut().set_blocked(false)
+    }
+
+    fn is_overridden(&self) -> bool {
+        self.input_blocker.borrow().is_overridden()
+    }
+}
+
+/// How often the lock state is checked.
+const LOCK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Locks the session and watches logind's `LockedHint` for it being
+/// unlocked before the break is over.
+pub struct LockSession<R> {
+    runner: R,
+    early_unlock: EarlyUnlock,
+    journal: Option<Journal>,
+    check_interval: Duration,
+    last_check: Option<Instant>,
+    locked_at: Instant,
+    /// The screen locker came up. Until then an unlocked session isn't an
+    /// early unlock, and it never is where `LockedHint` isn't supported.
+    seen_locked: bool,
+    cut_short: bool,
+}
+
+impl<R: CommandRunner> LockSession<R> {
+    pub fn new(runner: R, early_unlock: EarlyUnlock, journal: Option<Journal>) -> LockSession<R> {
+        LockSession {
+            runner,
+            early_unlock,
+            journal,
+            check_interval: LOCK_CHECK_INTERVAL,
+            last_check: None,
+            locked_at: Instant::now(),
+            seen_locked: false,
+            cut_short: false,
+        }
+    }
+
+    pub fn with_check_interval(mut self, check_interval: Duration) -> LockSession<R> {
+        self.check_interval = check_interval;
+        self
+    }
+
+    fn lock(&mut self) -> Result<()> {
+        self.seen_locked = false;
+        #[cfg(windows)]
+        return self
+            .runner
+            .run("rundll32.exe", &["user32.dll,LockWorkStation"]);
+        #[cfg(not(windows))]
+        self.runner.run("loginctl", &["lock-session"])
+    }
+
+    /// `None` where it can't be told.
+    fn is_locked(&self) -> Result<Option<bool>> {
+        #[cfg(windows)]
+        return Ok(None);
+        #[cfg(not(windows))]
+        {
+            let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".to_string());
+            let output = self.runner.output(
+                "loginctl",
+                &["show-session", &session, "--property=LockedHint", "--value"],
+            )?;
+            Ok(match output.trim() {
+                "yes" => Some(true),
+                "no" => Some(false),
+                _ => None,
+            })
+        }
+    }
+}
+
+impl<R: CommandRunner> EnforcementAction for LockSession<R> {
+    fn name(&self) -> &'static str {
+        "lock session"
+    }
+
+    fn start(&mut self) -> Result<()> {
+        self.locked_at = Instant::now();
+        self.lock()
+    }
+
+    fn update(&mut self) -> Result<()> {
+        if self.cut_short
+            || self
+                .last_check
+                .is_some_and(|last_check| last_check.elapsed() < self.check_interval)
+        {
+            return Ok(());
+        }
+        self.last_check = Some(Instant::now());
+        match self.is_locked()? {
+            Some(true) => self.seen_locked = true,
+            Some(false) if self.seen_locked => match self.early_unlock {
+                EarlyUnlock::Relock => self.lock()?,
+                EarlyUnlock::CutShort => {
+                    self.cut_short = true;
+                    if let Some(journal) = &self.journal {
+                        journal.record(JournalEvent::BreakCutShort {
+                            after: self.locked_at.elapsed(),
+                        })?;
+                    }
+                }
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn is_cut_short(&self) -> bool {
+        self.cut_short
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Power {
+    Suspend,
+    Hibernate,
+    Shutdown,
+}
+
+/// Suspends, hibernates or shuts down when the break starts. There's nothing
+/// to undo when it ends.
+pub struct PowerAction<R> {
+    power: Power,
+    runner: R,
+}
+
+impl<R: CommandRunner> PowerAction<R> {
+    pub fn new(power: Power, runner: R) -> PowerAction<R> {
+        PowerAction { power, runner }
+    }
+}
+
+impl<R: CommandRunner> EnforcementAction for PowerAction<R> {
+    fn name(&self) -> &'static str {
+        match self.power {
+            Power::Suspend => "suspend",
+            Power::Hibernate => "hibernate",
+            Power::Shutdown => "shut down",
+        }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        #[cfg(windows)]
+        let (program, args): (&str, &[&str]) = match self.power {
+            Power::Suspend => ("rundll32.exe", &["powrprof.dll,SetSuspendState", "0,1,0"]),
+            Power::Hibernate => ("shutdown", &["/h"]),
+            Power::Shutdown => ("shutdown", &["/s", "/t", "0"]),
+        };
+        #[cfg(not(windows))]
+        let (program, args): (&str, &[&str]) = match self.power {
+            Power::Suspend => ("systemctl", &["suspend"]),
+            Power::Hibernate => ("systemctl", &["hibernate"]),
+            Power::Shutdown => ("systemctl", &["poweroff"]),
+        };
+        self.runner.run(program, args)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum BreakPreference {
+    BlockInput { let_user_prepare: bool },
+    LockSession { let_user_prepare: bool },
+    Suspend { let_user_prepare: bool },
+    Hibernate { let_user_prepare: bool },
+    Shutdown { let_user_prepare: bool },
+}
+
+impl Default for BreakPreference {
+    fn default() -> Self {
+        BreakPreference::BlockInput {
+            let_user_prepare: true,
+        }
+    }
+}
+
+impl BreakPreference {
+    /// The choice remembered for `profile`, or else asks. Dialogs are tried
+    /// first, then the terminal, then it falls back to the default.
+    pub fn get(profile: &str) -> BreakPreference {
+        let mut state = State::load().unwrap_or_else(|e| {
+            eprintln!("{e:#}");
+            State::default()
+        });
+        if let Some(preference) = state.break_preferences.get(profile) {
+            return *preference;
+        }
+        let answer = ask(profile, dialog::default_backend()).or_else(|e| {
+            eprintln!("failed to show a dialog, asking on the terminal: {e}");
+            ask(profile, Box::new(dialog::backends::Stdio::new()))
+        });
+        match answer {
+            Ok(Some((preference, remember))) => {
+                if remember {
+                    state
+                        .break_preferences
+                        .insert(profile.to_string(), preference);
+                    match state.save() {
+                        Ok(path) => println!(
+                            "Remembered the break preference in {}, remove it there to be asked again.",
+                            path.display()
+                        ),
+                        Err(e) => eprintln!("failed to remember the break preference: {e:#}"),
+                    }
+                }
+                preference
+            }
+            Ok(None) => BreakPreference::default(),
+            Err(e) => {
+                eprintln!("failed to ask for a break preference: {e}");
+                BreakPreference::default()
+            }
+        }
+    }
+
+    /// Whether the action can be applied again to a break that was running
+    /// when the app stopped. Powering off is what stopped it.
+    pub fn can_resume(&self) -> bool {
+        matches!(
+            self,
+            BreakPreference::BlockInput { .. } | BreakPreference::LockSession { .. }
+        )
+    }
+
+    pub fn let_user_prepare(&self) -> bool {
+        match *self {
+            BreakPreference::BlockInput { let_user_prepare }
+            | BreakPreference::LockSession { let_user_prepare }
+            | BreakPreference::Suspend { let_user_prepare }
+            | BreakPreference::Hibernate { let_user_prepare }
+            | BreakPreference::Shutdown { let_user_prepare } => let_user_prepare,
+        }
+    }
+
+    pub fn action<R>(
+        &self,
+        config: &Config,
+        input_blocker: &Rc<RefCell<InputBlocker>>,
+        runner: R,
+    ) -> Result<Box<dyn EnforcementAction>>
+    where
+        R: CommandRunner + 'static,
+    {
+        Ok(match self {
+            BreakPreference::BlockInput { .. } => {
+                input_blocker
+                    .borrow_mut()
+                    .set_emergency_allowed(emergency::override_allowed(
+                        &config.blocking.emergency,
+                        Journal::open().as_ref(),
+                    ));
+                Box::new(BlockInput::new(input_blocker.clone()))
+            }
+            BreakPreference::LockSession { .. } => Box::new(LockSession::new(
+                runner,
+                config.lock.early_unlock_for(config.break_profile()),
+                Journal::open(),
+            )),
+            BreakPreference::Suspend { .. } => Box::new(PowerAction::new(Power::Suspend, runner)),
+            BreakPreference::Hibernate { .. } => {
+                Box::new(PowerAction::new(Power::Hibernate, runner))
+            }
+            BreakPreference::Shutdown { .. } => Box::new(PowerAction::new(Power::Shutdown, runner)),
+        })
+    }
+}
+
+const CHOICES: &str = "1. Block input\n\
+                       2. Lock the session\n\
+                       3. Suspend\n\
+                       4. Hibernate\n\
+                       5. Shut down";
+
+/// `let_user_prepare` is left to the caller.
+fn parse_choice(answer: &str, let_user_prepare: bool) -> Option<BreakPreference> {
+    Some(match answer.trim() {
+        // Enter alone takes the default.
+        "" | "1" => BreakPreference::BlockInput { let_user_prepare },
+        "2" => BreakPreference::LockSession { let_user_prepare },
+        "3" => BreakPreference::Suspend { let_user_prepare },
+        "4" => BreakPreference::Hibernate { let_user_prepare },
+        "5" => BreakPreference::Shutdown { let_user_prepare },
+        _ => return None,
+    })
+}
+
+/// The chosen preference and whether to remember it, or `None` if the user
+/// cancelled.
+fn ask(
+    profile: &str,
+    backend: Box<dyn Backend>,
+) -> dialog::Result<Option<(BreakPreference, bool)>> {
+    let title = "Break time";
+    let mut prompt = format!("How should this break be enforced?\n{CHOICES}");
+    let answer = loop {
+        let Some(answer) = dialog::Input::new(&prompt)
+            .title(title)
+            .default("1")
+            .show_with(&backend)?
+        else {
+            return Ok(None);
+        };
+        if parse_choice(&answer, true).is_some() {
+            break answer;
+        }
+        prompt = format!("`{}` isn't one of the choices.\n{CHOICES}", answer.trim());
+    };
+    let prepare = dialog::Question::new("Count down first, so you can save your work?")
+        .title(title)
+        .show_with(&backend)?;
+    if prepare == Choice::Cancel {
+        return Ok(None);
+    }
+    let Some(preference) = parse_choice(&answer, prepare == Choice::Yes) else {
+        unreachable!("checked above");
+    };
+    let remember = dialog::Question::new(format!(
+        "Do the same for every break with the `{profile}` profile, without asking again?"
+    ))
+    .title(title)
+    .show_with(&backend)?;
+    Ok(Some((preference, remember == Choice::Yes)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn preferences_run_their_commands() {
+        let runner = MockRunner::default();
+        let input_blocker = Rc::default();
+        let preferences = [
+            BreakPreference::LockSession {
+                let_user_prepare: false,
+            },
+            BreakPreference::Suspend {
+                let_user_prepare: false,
+            },
+            BreakPreference::Hibernate {
+                let_user_prepare: false,
+            },
+            BreakPreference::Shutdown {
+                let_user_prepare: false,
+            },
+        ];
+        for preference in preferences {
+            let mut action = preference
+                .action(&Config::default(), &input_blocker, runner.clone())
+                .unwrap();
+            action.start().unwrap();
+            action.update().unwrap();
+            action.end().unwrap();
+        }
+        let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".to_string());
+        assert_eq!(
+            runner.commands(),
+            vec![
+                "loginctl lock-session".to_string(),
+                format!("loginctl show-session {session} --property=LockedHint --value"),
+                "systemctl suspend".to_string(),
+                "systemctl hibernate".to_string(),
+                "systemctl poweroff".to_string(),
+            ]
+        );
+
+        let failing = MockRunner {
+            fail: true,
+            ..Default::default()
+        };
+        let mut action = PowerAction::new(Power::Suspend, failing);
+        assert!(action.start().is_err());
+    }
+
+    #[test]
+    fn early_unlock() {
+        let runner = MockRunner::default();
+        let set_locked = |locked: &str| *runner.output.lock().unwrap() = locked.to_string();
+        let path = std::env::temp_dir().join(format!("pomodoro-ss-journal-{}.log", process::id()));
+        let journal = Journal::new(path.clone());
+
+        let mut relock = LockSession::new(runner.clone(), EarlyUnlock::Relock, None)
+            .with_check_interval(Duration::ZERO);
+        relock.start().unwrap();
+        // The locker isn't up yet.
+        set_locked("no");
+        relock.update().unwrap();
+        set_locked("yes");
+        relock.update().unwrap();
+        set_locked("no");
+        relock.update().unwrap();
+        assert!(!relock.is_cut_short());
+        let locks = |runner: &MockRunner| {
+            runner
+                .commands()
+                .iter()
+                .filter(|c| *c == "loginctl lock-session")
+                .count()
+        };
+        assert_eq!(locks(&runner), 2);
+
+        let mut cut_short =
+            LockSession::new(runner.clone(), EarlyUnlock::CutShort, Some(journal.clone()))
+                .with_check_interval(Duration::ZERO);
+        cut_short.start().unwrap();
+        set_locked("yes");
+        cut_short.update().unwrap();
+        set_locked("no");
+        cut_short.update().unwrap();
+        assert!(cut_short.is_cut_short());
+        assert_eq!(locks(&runner), 3);
+        assert!(matches!(
+            journal.entries().unwrap()[..],
+            [crate::journal::JournalEntry {
+                event: JournalEvent::BreakCutShort { .. },
+                ..
+            }]
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn choices() {
+        assert_eq!(parse_choice(" ", true), Some(BreakPreference::default()));
+        assert_eq!(
+            parse_choice("4\n", false),
+            Some(BreakPreference::Hibernate {
+                let_user_prepare: false
+            })
+        );
+        assert_eq!(parse_choice("6", true), None);
+        assert!(BreakPreference::default().can_resume());
+        assert!(!parse_choice("5", false).unwrap().can_resume());
+    }
+}
@@ -0,0 +1,277 @@
+//! Exclusive grabs of evdev devices, so their input reaches nothing else.
+
+use std::{
+    io,
+    os::fd::AsRawFd,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use evdev::{uinput::VirtualDevice, AttributeSet, Device, EventType, InputEvent, Key};
+
+use crate::{
+    evdev_input::{event_device_paths, is_keyboard_or_pointer, set_nonblocking},
+    safety::{self, Release},
+};
+
+/// How often newly plugged in devices are looked for.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The name of the device passed through keys come out of, so it's never
+/// grabbed itself.
+const PASSTHROUGH_NAME: &str = "pomodoro-ss passthrough";
+
+/// `_IOW('E', 0x90, int)`, with 0 as the argument it releases a grab.
+const EVIOCGRAB: u64 = 0x4004_4590;
+
+/// Every keyboard and pointer, grabbed with `EVIOCGRAB` until this is
+/// dropped. Dropping happens on unwinding too, and the kernel releases the
+/// grab if the process dies outright.
+pub struct EvdevGrab {
+    devices: Vec<(PathBuf, Device)>,
+    last_scan: Instant,
+    /// Lets go of the grabs from the panic hook, before anything unwinds.
+    release: Option<Release>,
+}
+
+impl EvdevGrab {
+    /// Fails if there's nothing that could be grabbed.
+    pub fn grab_all() -> Result<EvdevGrab> {
+        let mut grab = EvdevGrab {
+            devices: Vec::new(),
+            last_scan: Instant::now(),
+            release: None,
+        };
+        grab.scan()?;
+        grab.register_release();
+        if grab.devices.is_empty() {
+            bail!(
+                "no keyboard or pointer could be grabbed, \
+                 add the user to the `input` group or run with access to it"
+            );
+        }
+        Ok(grab)
+    }
+
+    fn scan(&mut self) -> io::Result<()> {
+        self.last_scan = Instant::now();
+        for path in event_device_paths()? {
+            if self.devices.iter().any(|(grabbed, _)| *grabbed == path) {
+                continue;
+            }
+            let Ok(mut device) = Device::open(&path) else {
+                continue;
+            };
+            if !is_keyboard_or_pointer(&device) || device.name() == Some(PASSTHROUGH_NAME) {
+                continue;
+            }
+            if let Err(e) = set_nonblocking(&device) {
+                eprintln!("failed to set up {}: {e}", path.display());
+                continue;
+            }
+            match device.grab() {
+                Ok(()) => self.devices.push((path, device)),
+                Err(e) => eprintln!("failed to grab {}: {e}", path.display()),
+            }
+        }
+        Ok(())
+    }
+
+    fn register_release(&mut self) {
+        // Drop the old one first, the fds may be gone.
+        self.release = None;
+        let fds: Vec<_> = self
+            .devices
+            .iter()
+            .map(|(_, device)| device.as_raw_fd())
+            .collect();
+        self.release = Some(safety::on_release(move || {
+            for fd in &fds {
+                // SAFETY: Unregistered before the devices close, so the fds
+                // are still theirs.
+                unsafe { libc::ioctl(*fd, EVIOCGRAB as _, 0) };
+            }
+        }));
+    }
+
+    /// Grabs devices plugged in since the last scan, and forgets unplugged
+    /// ones. Cheap to call every frame.
+    pub fn refresh(&mut self) {
+        if self.last_scan.elapsed() < RESCAN_INTERVAL {
+            return;
+        }
+        self.release = None;
+        self.devices.retain(|(path, _)| path.exists());
+        if let Err(e) = self.scan() {
+            eprintln!("failed to rescan input devices: {e}");
+        }
+        self.register_release();
+    }
+
+    /// Everything the grabbed devices sent since the last call. Nothing
+    /// else sees these events.
+    pub fn pump(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        for (_, device) in &mut self.devices {
+            // `WouldBlock` when there's nothing new.
+            if let Ok(fetched) = device.fetch_events() {
+                events.extend(fetched);
+            }
+        }
+        events
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+}
+
+/// Parses evdev key names like `KEY_VOLUMEUP`.
+pub fn parse_key(name: &str) -> Result<Key> {
+    Key::from_str(name).map_err(|_| anyhow::anyhow!("unknown key `{name}`"))
+}
+
+/// Re-emits allow-listed keys from grabbed devices through a uinput device,
+/// so they keep working while everything else is blocked.
+pub struct Passthrough {
+    allowed: Vec<Key>,
+    device: VirtualDevice,
+}
+
+impl Passthrough {
+    pub fn new(allowed: Vec<Key>) -> Result<Passthrough> {
+        let mut keys = AttributeSet::<Key>::new();
+        for key in &allowed {
+            keys.insert(*key);
+        }
+        let device = evdev::uinput::VirtualDeviceBuilder::new()
+            .context("failed to open /dev/uinput")?
+            .name(PASSTHROUGH_NAME)
+            .with_keys(&keys)?
+            .build()?;
+        Ok(Passthrough { allowed, device })
+    }
+
+    pub fn forward(&mut self, events: &[InputEvent]) -> Result<()> {
+        let allowed: Vec<InputEvent> = events
+            .iter()
+            .filter(|event| {
+                event.event_type() == EventType::KEY
+                    && self.allowed.contains(&Key::new(event.code()))
+            })
+            .copied()
+            .collect();
+        if !allowed.is_empty() {
+            // `emit` adds the report that ends the batch.
+            self.device.emit(&allowed)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for EvdevGrab {
+    fn drop(&mut self) {
+        self.release = None;
+        for (path, device) in &mut self.devices {
+            if let Err(e) = device.ungrab() {
+                // Closing the device below releases it regardless.
+                eprintln!("failed to release {}: {e}", path.display());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{evdev_input::EvdevSource, input_source::InputSource};
+    use device_query::Keycode;
+    use evdev::{uinput::VirtualDeviceBuilder, AttributeSet, EventType, InputEvent, Key};
+    use std::thread;
+
+    #[test]
+    fn parses_key_names() {
+        assert_eq!(parse_key("KEY_VOLUMEUP").unwrap(), Key::KEY_VOLUMEUP);
+        assert!(parse_key("KEY_VOLUME_UP").is_err());
+    }
+
+    #[test]
+    #[ignore = "needs write access to /dev/uinput and read access to /dev/input"]
+    fn passes_allowed_keys_through() {
+        let mut keys = AttributeSet::<Key>::new();
+        keys.insert(Key::KEY_A);
+        keys.insert(Key::KEY_VOLUMEUP);
+        let mut device = VirtualDeviceBuilder::new()
+            .unwrap()
+            .name("pomodoro-ss passthrough test device")
+            .with_keys(&keys)
+            .unwrap()
+            .build()
+            .unwrap();
+        thread::sleep(Duration::from_millis(500));
+        let mut grab = EvdevGrab::grab_all().unwrap();
+        let mut passthrough = Passthrough::new(vec![Key::KEY_VOLUMEUP]).unwrap();
+        thread::sleep(Duration::from_millis(500));
+        let node = passthrough
+            .device
+            .enumerate_dev_nodes_blocking()
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let mut output = Device::open(node).unwrap();
+
+        let key = |key: Key| InputEvent::new(EventType::KEY, key.code(), 1);
+        device
+            .emit(&[key(Key::KEY_A), key(Key::KEY_VOLUMEUP)])
+            .unwrap();
+        thread::sleep(Duration::from_millis(200));
+        passthrough.forward(&grab.pump()).unwrap();
+
+        let forwarded: Vec<_> = output
+            .fetch_events()
+            .unwrap()
+            .filter(|event| event.event_type() == EventType::KEY)
+            .map(|event| Key::new(event.code()))
+            .collect();
+        assert_eq!(forwarded, vec![Key::KEY_VOLUMEUP]);
+    }
+
+    #[test]
+    #[ignore = "needs write access to /dev/uinput and read access to /dev/input"]
+    fn grabbed_input_reaches_nobody_else() {
+        let mut keys = AttributeSet::<Key>::new();
+        keys.insert(Key::KEY_A);
+        let mut device = VirtualDeviceBuilder::new()
+            .unwrap()
+            .name("pomodoro-ss grab test device")
+            .with_keys(&keys)
+            .unwrap()
+            .build()
+            .unwrap();
+        thread::sleep(Duration::from_millis(500));
+        let mut source = EvdevSource::new().unwrap();
+        let mut press = |value| {
+            device
+                .emit(&[InputEvent::new(EventType::KEY, Key::KEY_A.code(), value)])
+                .unwrap();
+            thread::sleep(Duration::from_millis(200));
+        };
+
+        let grab = EvdevGrab::grab_all().unwrap();
+        press(1);
+        assert!(source.sample().keys.is_empty());
+        press(0);
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+            let _grab = grab;
+            panic!("break went wrong");
+        }));
+        assert!(result.is_err());
+        press(1);
+        assert_eq!(source.sample().keys, vec![Keycode::A]);
+        press(0);
+    }
+}
@@ -0,0 +1,429 @@
+//! Input straight from the kernel's evdev devices, which works under Wayland
+//! where polling the X server sees nothing.
+//!
+//! Reading `/dev/input/event*` usually requires being in the `input` group.
+
+use std::{
+    collections::HashSet,
+    fs, io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{bail, Result};
+use device_query::Keycode;
+use evdev::{AbsoluteAxisType, Device, InputEventKind, Key, RelativeAxisType};
+
+use crate::input_source::{InputSnapshot, InputSource};
+
+const INPUT_DIR: &str = "/dev/input";
+/// How often `/dev/input` is rescanned for plugged in devices.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Default)]
+struct SharedState {
+    keys: HashSet<Key>,
+    /// 1-based like `device_query`: left, middle, right.
+    mouse_buttons: [bool; 6],
+    mouse_coord: (i32, i32),
+    scroll: (i32, i32),
+    open_devices: HashSet<PathBuf>,
+    warned_devices: HashSet<PathBuf>,
+}
+
+pub struct EvdevSource {
+    state: Arc<Mutex<SharedState>>,
+    /// The write end of the pipe the reader threads poll next to their
+    /// device. Closing it with the source wakes them up to exit, which
+    /// closes the devices.
+    _shutdown: OwnedFd,
+}
+
+impl EvdevSource {
+    /// Starts reading every keyboard and pointer, including ones plugged in
+    /// later. Fails if none of them can be read.
+    pub fn new() -> Result<EvdevSource> {
+        let state = Arc::new(Mutex::new(SharedState::default()));
+        let (shutdown_read, shutdown) = pipe()?;
+        let shutdown_read = Arc::new(shutdown_read);
+        let opened = scan_devices(&state, &shutdown_read)?;
+        if opened == 0 {
+            bail!(
+                "no readable keyboard or pointer in {INPUT_DIR}, \
+                 add the user to the `input` group or run with access to it"
+            );
+        }
+        let rescan_state = Arc::downgrade(&state);
+        thread::spawn(move || loop {
+            thread::sleep(RESCAN_INTERVAL);
+            let Some(state) = rescan_state.upgrade() else {
+                break;
+            };
+            if let Err(e) = scan_devices(&state, &shutdown_read) {
+                eprintln!("failed to rescan input devices: {e:#}");
+            }
+        });
+        Ok(EvdevSource {
+            state,
+            _shutdown: shutdown,
+        })
+    }
+}
+
+impl InputSource for EvdevSource {
+    fn sample(&mut self) -> InputSnapshot {
+        let mut state = self.state.lock().unwrap();
+        InputSnapshot {
+            mouse_coord: state.mouse_coord,
+            mouse_buttons: state.mouse_buttons.to_vec(),
+            keys: state.keys.iter().filter_map(|&key| keycode(key)).collect(),
+            scroll: std::mem::take(&mut state.scroll),
+        }
+    }
+}
+
+/// Whether the device is something a person types or points with.
+pub fn is_keyboard_or_pointer(device: &Device) -> bool {
+    let keys = device.supported_keys();
+    keys.is_some_and(|keys| keys.contains(Key::KEY_A) || keys.contains(Key::BTN_LEFT))
+        || device
+            .supported_relative_axes()
+            .is_some_and(|axes| axes.contains(RelativeAxisType::REL_X))
+}
+
+/// Every `/dev/input/event*` node, sorted.
+pub fn event_device_paths() -> io::Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(INPUT_DIR)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("event"))
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+pub fn set_nonblocking(device: &Device) -> io::Result<()> {
+    let fd = device.as_raw_fd();
+    // SAFETY: `fd` is open for as long as `device` is.
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// The read and write ends of a new pipe.
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two fds, which are owned from here on.
+    unsafe {
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])))
+    }
+}
+
+/// Waits until `device` can be read, or returns `false` once the write end
+/// of `shutdown` is closed.
+fn wait_readable(device: &Device, shutdown: &OwnedFd) -> io::Result<bool> {
+    let mut fds = [device.as_raw_fd(), shutdown.as_raw_fd()].map(|fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    // SAFETY: Both fds are open for the duration of the call.
+    while unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+    Ok(fds[1].revents == 0)
+}
+
+/// Opens devices that aren't being read yet and returns how many are.
+fn scan_devices(state: &Arc<Mutex<SharedState>>, shutdown: &Arc<OwnedFd>) -> Result<usize> {
+    for path in event_device_paths()? {
+        if state.lock().unwrap().open_devices.contains(&path) {
+            continue;
+        }
+        let device = match Device::open(&path) {
+            Ok(device) => device,
+            Err(e) => {
+                let mut state = state.lock().unwrap();
+                if state.warned_devices.insert(path.clone()) {
+                    if e.kind() == io::ErrorKind::PermissionDenied {
+                        eprintln!(
+                            "no permission to read {}, is the user in the `input` group?",
+                            path.display()
+                        );
+                    } else {
+                        eprintln!("failed to open {}: {e}", path.display());
+                    }
+                }
+                continue;
+            }
+        };
+        if !is_keyboard_or_pointer(&device) {
+            continue;
+        }
+        if let Err(e) = set_nonblocking(&device) {
+            eprintln!("failed to set up {}: {e}", path.display());
+            continue;
+        }
+        state.lock().unwrap().open_devices.insert(path.clone());
+        let state = Arc::downgrade(state);
+        let shutdown = shutdown.clone();
+        thread::spawn(move || read_device(&path, device, state, &shutdown));
+    }
+    Ok(state.lock().unwrap().open_devices.len())
+}
+
+fn read_device(
+    path: &Path,
+    mut device: Device,
+    state: std::sync::Weak<Mutex<SharedState>>,
+    shutdown: &OwnedFd,
+) {
+    // Touchpads report absolute positions, only the change moves the pointer.
+    let mut last_abs: (Option<i32>, Option<i32>) = (None, None);
+    loop {
+        match wait_readable(&device, shutdown) {
+            Ok(true) => {}
+            // The source is gone.
+            Ok(false) => return,
+            Err(e) => {
+                eprintln!("failed to wait for {}: {e}", path.display());
+                break;
+            }
+        }
+        let events = match device.fetch_events() {
+            Ok(events) => events.collect::<Vec<_>>(),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            // Unplugged
+            Err(_) => break,
+        };
+        let Some(state) = state.upgrade() else {
+            return;
+        };
+        let mut state = state.lock().unwrap();
+        for event in events {
+            match event.kind() {
+                InputEventKind::Key(key) => {
+                    let pressed = event.value() != 0;
+                    match mouse_button_index(key) {
+                        Some(index) => state.mouse_buttons[index] = pressed,
+                        None if key == Key::BTN_TOUCH && !pressed => last_abs = (None, None),
+                        None if pressed => {
+                            state.keys.insert(key);
+                        }
+                        None => {
+                            state.keys.remove(&key);
+                        }
+                    }
+                }
+                InputEventKind::RelAxis(axis) => match axis {
+                    RelativeAxisType::REL_X => state.mouse_coord.0 += event.value(),
+                    RelativeAxisType::REL_Y => state.mouse_coord.1 += event.value(),
+                    RelativeAxisType::REL_WHEEL => state.scroll.0 += event.value(),
+                    RelativeAxisType::REL_HWHEEL => state.scroll.1 += event.value(),
+                    _ => {}
+                },
+                InputEventKind::AbsAxis(axis) => {
+                    let (last, coord) = match axis {
+                        AbsoluteAxisType::ABS_X => (&mut last_abs.0, &mut state.mouse_coord.0),
+                        AbsoluteAxisType::ABS_Y => (&mut last_abs.1, &mut state.mouse_coord.1),
+                        _ => continue,
+                    };
+                    if let Some(last) = last {
+                        *coord += event.value() - *last;
+                    }
+                    *last = Some(event.value());
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Some(state) = state.upgrade() {
+        state.lock().unwrap().open_devices.remove(path);
+    }
+}
+
+fn mouse_button_index(key: Key) -> Option<usize> {
+    match key {
+        Key::BTN_LEFT => Some(1),
+        Key::BTN_MIDDLE => Some(2),
+        Key::BTN_RIGHT => Some(3),
+        Key::BTN_SIDE => Some(4),
+        Key::BTN_EXTRA => Some(5),
+        _ => None,
+    }
+}
+
+pub fn keycode(key: Key) -> Option<Keycode> {
+    Some(match key {
+        Key::KEY_0 => Keycode::Key0,
+        Key::KEY_1 => Keycode::Key1,
+        Key::KEY_2 => Keycode::Key2,
+        Key::KEY_3 => Keycode::Key3,
+        Key::KEY_4 => Keycode::Key4,
+        Key::KEY_5 => Keycode::Key5,
+        Key::KEY_6 => Keycode::Key6,
+        Key::KEY_7 => Keycode::Key7,
+        Key::KEY_8 => Keycode::Key8,
+        Key::KEY_9 => Keycode::Key9,
+        Key::KEY_A => Keycode::A,
+        Key::KEY_B => Keycode::B,
+        Key::KEY_C => Keycode::C,
+        Key::KEY_D => Keycode::D,
+        Key::KEY_E => Keycode::E,
+        Key::KEY_F => Keycode::F,
+        Key::KEY_G => Keycode::G,
+        Key::KEY_H => Keycode::H,
+        Key::KEY_I => Keycode::I,
+        Key::KEY_J => Keycode::J,
+        Key::KEY_K => Keycode::K,
+        Key::KEY_L => Keycode::L,
+        Key::KEY_M => Keycode::M,
+        Key::KEY_N => Keycode::N,
+        Key::KEY_O => Keycode::O,
+        Key::KEY_P => Keycode::P,
+        Key::KEY_Q => Keycode::Q,
+        Key::KEY_R => Keycode::R,
+        Key::KEY_S => Keycode::S,
+        Key::KEY_T => Keycode::T,
+        Key::KEY_U => Keycode::U,
+        Key::KEY_V => Keycode::V,
+        Key::KEY_W => Keycode::W,
+        Key::KEY_X => Keycode::X,
+        Key::KEY_Y => Keycode::Y,
+        Key::KEY_Z => Keycode::Z,
+        Key::KEY_F1 => Keycode::F1,
+        Key::KEY_F2 => Keycode::F2,
+        Key::KEY_F3 => Keycode::F3,
+        Key::KEY_F4 => Keycode::F4,
+        Key::KEY_F5 => Keycode::F5,
+        Key::KEY_F6 => Keycode::F6,
+        Key::KEY_F7 => Keycode::F7,
+        Key::KEY_F8 => Keycode::F8,
+        Key::KEY_F9 => Keycode::F9,
+        Key::KEY_F10 => Keycode::F10,
+        Key::KEY_F11 => Keycode::F11,
+        Key::KEY_F12 => Keycode::F12,
+        Key::KEY_ESC => Keycode::Escape,
+        Key::KEY_SPACE => Keycode::Space,
+        Key::KEY_LEFTCTRL => Keycode::LControl,
+        Key::KEY_RIGHTCTRL => Keycode::RControl,
+        Key::KEY_LEFTSHIFT => Keycode::LShift,
+        Key::KEY_RIGHTSHIFT => Keycode::RShift,
+        Key::KEY_LEFTALT => Keycode::LAlt,
+        Key::KEY_RIGHTALT => Keycode::RAlt,
+        Key::KEY_LEFTMETA | Key::KEY_RIGHTMETA => Keycode::Meta,
+        Key::KEY_ENTER | Key::KEY_KPENTER => Keycode::Enter,
+        Key::KEY_UP => Keycode::Up,
+        Key::KEY_DOWN => Keycode::Down,
+        Key::KEY_LEFT => Keycode::Left,
+        Key::KEY_RIGHT => Keycode::Right,
+        Key::KEY_BACKSPACE => Keycode::Backspace,
+        Key::KEY_CAPSLOCK => Keycode::CapsLock,
+        Key::KEY_TAB => Keycode::Tab,
+        Key::KEY_HOME => Keycode::Home,
+        Key::KEY_END => Keycode::End,
+        Key::KEY_PAGEUP => Keycode::PageUp,
+        Key::KEY_PAGEDOWN => Keycode::PageDown,
+        Key::KEY_INSERT => Keycode::Insert,
+        Key::KEY_DELETE => Keycode::Delete,
+        Key::KEY_KP0 => Keycode::Numpad0,
+        Key::KEY_KP1 => Keycode::Numpad1,
+        Key::KEY_KP2 => Keycode::Numpad2,
+        Key::KEY_KP3 => Keycode::Numpad3,
+        Key::KEY_KP4 => Keycode::Numpad4,
+        Key::KEY_KP5 => Keycode::Numpad5,
+        Key::KEY_KP6 => Keycode::Numpad6,
+        Key::KEY_KP7 => Keycode::Numpad7,
+        Key::KEY_KP8 => Keycode::Numpad8,
+        Key::KEY_KP9 => Keycode::Numpad9,
+        Key::KEY_KPMINUS => Keycode::NumpadSubtract,
+        Key::KEY_KPPLUS => Keycode::NumpadAdd,
+        Key::KEY_KPSLASH => Keycode::NumpadDivide,
+        Key::KEY_KPASTERISK => Keycode::NumpadMultiply,
+        Key::KEY_GRAVE => Keycode::Grave,
+        Key::KEY_MINUS => Keycode::Minus,
+        Key::KEY_EQUAL => Keycode::Equal,
+        Key::KEY_LEFTBRACE => Keycode::LeftBracket,
+        Key::KEY_RIGHTBRACE => Keycode::RightBracket,
+        Key::KEY_BACKSLASH => Keycode::BackSlash,
+        Key::KEY_SEMICOLON => Keycode::Semicolon,
+        Key::KEY_APOSTROPHE => Keycode::Apostrophe,
+        Key::KEY_COMMA => Keycode::Comma,
+        Key::KEY_DOT => Keycode::Dot,
+        Key::KEY_SLASH => Keycode::Slash,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use evdev::{uinput::VirtualDeviceBuilder, AttributeSet, EventType, InputEvent};
+
+    #[test]
+    #[ignore = "needs write access to /dev/uinput and read access to /dev/input"]
+    fn reads_virtual_devices() {
+        let mut keys = AttributeSet::<Key>::new();
+        keys.insert(Key::KEY_A);
+        keys.insert(Key::BTN_LEFT);
+        let mut axes = AttributeSet::<RelativeAxisType>::new();
+        axes.insert(RelativeAxisType::REL_X);
+        axes.insert(RelativeAxisType::REL_Y);
+        axes.insert(RelativeAxisType::REL_WHEEL);
+        let mut device = VirtualDeviceBuilder::new()
+            .unwrap()
+            .name("pomodoro-ss test device")
+            .with_keys(&keys)
+            .unwrap()
+            .with_relative_axes(&axes)
+            .unwrap()
+            .build()
+            .unwrap();
+        // Give udev a moment to create the node.
+        thread::sleep(Duration::from_millis(500));
+        let mut source = EvdevSource::new().unwrap();
+
+        let event = |type_, code, value| InputEvent::new(type_, code, value);
+        device
+            .emit(&[
+                event(EventType::KEY, Key::KEY_A.code(), 1),
+                event(EventType::KEY, Key::BTN_LEFT.code(), 1),
+                event(EventType::RELATIVE, RelativeAxisType::REL_X.0, 30),
+                event(EventType::RELATIVE, RelativeAxisType::REL_Y.0, -40),
+                event(EventType::RELATIVE, RelativeAxisType::REL_WHEEL.0, -2),
+            ])
+            .unwrap();
+        thread::sleep(Duration::from_millis(200));
+
+        let snapshot = source.sample();
+        assert_eq!(snapshot.keys, vec![Keycode::A]);
+        assert!(snapshot.mouse_buttons[1]);
+        assert_eq!(snapshot.mouse_coord, (30, -40));
+        assert_eq!(snapshot.scroll, (-2, 0));
+        // Scrolling is reported once.
+        assert_eq!(source.sample().scroll, (0, 0));
+
+        device
+            .emit(&[event(EventType::KEY, Key::KEY_A.code(), 0)])
+            .unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert!(source.sample().keys.is_empty());
+    }
+}
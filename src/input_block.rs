@@ -0,0 +1,194 @@
+//! Keeps keyboard and mouse input from reaching anything during a break.
+//!
+//! On Windows this is `BlockInput`, which needs to run elevated. On Linux the
+//! evdev devices are grabbed, which needs access to `/dev/input`, and the
+//! allow-listed keys are passed through a uinput device. Holding the
+//! emergency chord lets go of the grab.
+
+use anyhow::Result;
+
+use crate::config::BlockingConfig;
+
+#[derive(Default)]
+pub struct InputBlocker {
+    blocked: bool,
+    #[cfg(target_os = "linux")]
+    grab: Option<crate::evdev_grab::EvdevGrab>,
+    #[cfg(target_os = "linux")]
+    passthrough_keys: Vec<evdev::Key>,
+    /// Created the first time input is blocked and kept, so the virtual
+    /// device doesn't come and go with every break.
+    #[cfg(target_os = "linux")]
+    passthrough: Option<crate::evdev_grab::Passthrough>,
+    #[cfg(target_os = "linux")]
+    emergency_chord: Option<crate::emergency::KeyChord>,
+    /// Today's override budget is spent.
+    emergency_disabled: bool,
+    overridden: bool,
+    #[cfg(windows)]
+    release: Option<crate::safety::Release>,
+}
+
+impl InputBlocker {
+    pub fn new() -> InputBlocker {
+        InputBlocker::default()
+    }
+
+    /// Fails on keys that aren't known.
+    pub fn from_config(config: &BlockingConfig) -> Result<InputBlocker> {
+        #[cfg(target_os = "linux")]
+        {
+            let mut input_blocker = InputBlocker::new();
+            input_blocker.passthrough_keys = config
+                .passthrough_keys
+                .iter()
+                .map(|name| crate::evdev_grab::parse_key(name))
+                .collect::<Result<_>>()?;
+            input_blocker.emergency_chord =
+                crate::emergency::KeyChord::from_config(&config.emergency)?;
+            Ok(input_blocker)
+        }
+        #[cfg(not(target_os = "linux"))]
+        Ok(InputBlocker::new())
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+    }
+
+    /// For when the override budget is spent. The blocker outlives breaks,
+    /// so this is decided again before each one.
+    pub fn set_emergency_allowed(&mut self, allowed: bool) {
+        self.emergency_disabled = !allowed;
+    }
+
+    /// The emergency chord was held during this block. Input is already
+    /// let go by then.
+    pub fn is_overridden(&self) -> bool {
+        self.overridden
+    }
+
+    /// Meant to be called every frame. Failing to block is only reported
+    /// once per break rather than retried every frame.
+    pub fn set_blocked(&mut self, blocked: bool) -> Result<()> {
+        if blocked == self.blocked {
+            #[cfg(target_os = "linux")]
+            if let Some(grab) = self.grab.as_mut() {
+                grab.refresh();
+                let events = grab.pump();
+                self.handle_events(&events, std::time::Instant::now());
+            }
+            #[cfg(windows)]
+            if blocked {
+                // Ctrl+Alt+Del lifts the block, put it back.
+                let _res = block_input(true);
+            }
+            return Ok(());
+        }
+        self.blocked = blocked;
+        self.overridden = false;
+        #[cfg(target_os = "linux")]
+        {
+            self.grab = None;
+            if blocked {
+                if self.passthrough.is_none() && !self.passthrough_keys.is_empty() {
+                    match crate::evdev_grab::Passthrough::new(self.passthrough_keys.clone()) {
+                        Ok(passthrough) => self.passthrough = Some(passthrough),
+                        Err(e) => eprintln!("no keys pass through during breaks: {e:#}"),
+                    }
+                }
+                self.grab = Some(crate::evdev_grab::EvdevGrab::grab_all()?);
+            }
+        }
+        #[cfg(windows)]
+        {
+            self.release = None;
+            block_input(blocked)?;
+            if blocked {
+                self.release = Some(crate::safety::on_release(|| {
+                    let _res = block_input(false);
+                }));
+            }
+        }
+        Ok(())
+    }
+
+    /// Passes grabbed events through and lets go if they complete the
+    /// emergency chord.
+    #[cfg(target_os = "linux")]
+    fn handle_events(&mut self, events: &[evdev::InputEvent], now: std::time::Instant) {
+        if let Some(passthrough) = self.passthrough.as_mut() {
+            if let Err(e) = passthrough.forward(events) {
+                eprintln!("stopped passing keys through: {e:#}");
+                self.passthrough = None;
+            }
+        }
+        if self.emergency_disabled {
+            return;
+        }
+        if let Some(chord) = self.emergency_chord.as_mut() {
+            if chord.feed(events, now) {
+                self.overridden = true;
+                self.grab = None;
+            }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn grab_mut(&mut self) -> Option<&mut crate::evdev_grab::EvdevGrab> {
+        self.grab.as_mut()
+    }
+}
+
+impl Drop for InputBlocker {
+    fn drop(&mut self) {
+        if let Err(e) = self.set_blocked(false) {
+            eprintln!("failed to unblock input: {e:#}");
+        }
+    }
+}
+
+#[cfg(windows)]
+fn block_input(block: bool) -> std::result::Result<(), windows::core::Error> {
+    unsafe { windows::Win32::UI::Input::KeyboardAndMouse::BlockInput(block).ok() }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+    use crate::{
+        config::EmergencyConfig,
+        emergency::{self, KeyChord},
+        journal::{Journal, JournalEvent},
+    };
+    use evdev::{EventType, InputEvent, Key};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn spent_budget_keeps_input_blocked() {
+        let path =
+            std::env::temp_dir().join(format!("pomodoro-ss-budget-{}.log", std::process::id()));
+        let journal = Journal::new(path.clone());
+        journal
+            .record(JournalEvent::EmergencyOverride {
+                reason: "deploy".to_string(),
+            })
+            .unwrap();
+        let config = EmergencyConfig {
+            daily_budget: Some(1),
+            ..Default::default()
+        };
+
+        let mut input_blocker = InputBlocker::new();
+        input_blocker.emergency_chord = Some(KeyChord::new(vec![Key::KEY_ESC], Duration::ZERO));
+        let esc = [InputEvent::new(EventType::KEY, Key::KEY_ESC.code(), 1)];
+        input_blocker.set_emergency_allowed(emergency::override_allowed(&config, Some(&journal)));
+        input_blocker.handle_events(&esc, Instant::now());
+        assert!(!input_blocker.is_overridden());
+
+        input_blocker.set_emergency_allowed(true);
+        input_blocker.handle_events(&esc, Instant::now());
+        assert!(input_blocker.is_overridden());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use device_query::{DeviceQuery, DeviceState, Keycode};
+
+use anyhow::{Context, Result};
+
+use crate::config::InputBackend;
+
+/// The state of the input devices at one point in time.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InputSnapshot {
+    pub mouse_coord: (i32, i32),
+    /// Pressed state per mouse button, indexed by button number.
+    pub mouse_buttons: Vec<bool>,
+    pub keys: Vec<Keycode>,
+    /// Scroll wheel steps since the previous snapshot, vertical and
+    /// horizontal.
+    pub scroll: (i32, i32),
+}
+
+pub trait InputSource {
+    fn sample(&mut self) -> InputSnapshot;
+}
+
+/// Something that already knows how long ago the last input was.
+pub trait IdleSource {
+    fn idle_time(&mut self) -> Option<Duration>;
+}
+
+impl<S: InputSource + ?Sized> InputSource for Box<S> {
+    fn sample(&mut self) -> InputSnapshot {
+        (**self).sample()
+    }
+}
+
+impl<I: IdleSource + ?Sized> IdleSource for Box<I> {
+    fn idle_time(&mut self) -> Option<Duration> {
+        (**self).idle_time()
+    }
+}
+
+/// Opens the configured backend, falling back to `device_query`.
+pub fn open(backend: InputBackend) -> Result<Box<dyn InputSource>> {
+    #[cfg(target_os = "linux")]
+    {
+        // The X server can't be polled for input under Wayland.
+        let wayland = std::env::var_os("WAYLAND_DISPLAY").is_some();
+        if backend == InputBackend::Evdev || (backend == InputBackend::Auto && wayland) {
+            match crate::evdev_input::EvdevSource::new() {
+                Ok(source) => return Ok(Box::new(source)),
+                Err(e) => eprintln!("evdev input unavailable: {e:#}"),
+            }
+        }
+    }
+    Ok(Box::new(DeviceQuerySource::new()?))
+}
+
+/// The system's own idle time, when the platform offers one.
+pub fn open_idle_source() -> Option<Box<dyn IdleSource>> {
+    #[cfg(target_os = "linux")]
+    if std::env::var_os("DISPLAY").is_some() {
+        match crate::x11::ScreenSaverIdle::connect() {
+            Ok(idle) => return Some(Box::new(idle)),
+            Err(e) => eprintln!("X11 idle time unavailable: {e:#}"),
+        }
+    }
+    None
+}
+
+/// Polls the OS through `device_query`.
+pub struct DeviceQuerySource {
+    device_state: DeviceState,
+}
+
+impl DeviceQuerySource {
+    /// Fails without an X display, under Wayland for one.
+    pub fn new() -> Result<DeviceQuerySource> {
+        // `DeviceState::new` panics when it can't open the display, and this
+        // version has no checked constructor. Try the display first.
+        #[cfg(target_os = "linux")]
+        x11rb::connect(None).context("no X display to poll input from")?;
+        Ok(DeviceQuerySource {
+            device_state: DeviceState::new(),
+        })
+    }
+}
+
+impl InputSource for DeviceQuerySource {
+    fn sample(&mut self) -> InputSnapshot {
+        let mouse = self.device_state.get_mouse();
+        InputSnapshot {
+            mouse_coord: mouse.coords,
+            mouse_buttons: mouse.button_pressed,
+            keys: self.device_state.get_keys(),
+            scroll: (0, 0),
+        }
+    }
+}
+
+/// Never sees any input, for feeding a monitor by hand.
+pub struct NoInput;
+
+impl InputSource for NoInput {
+    fn sample(&mut self) -> InputSnapshot {
+        InputSnapshot::default()
+    }
+}
+
+/// Plays back snapshots in order, then keeps repeating the last one.
+#[cfg(test)]
+pub struct ScriptedInput {
+    snapshots: std::collections::VecDeque<InputSnapshot>,
+    last: InputSnapshot,
+}
+
+#[cfg(test)]
+impl ScriptedInput {
+    pub fn new(snapshots: impl IntoIterator<Item = InputSnapshot>) -> ScriptedInput {
+        ScriptedInput {
+            snapshots: snapshots.into_iter().collect(),
+            last: InputSnapshot::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl InputSource for ScriptedInput {
+    fn sample(&mut self) -> InputSnapshot {
+        if let Some(snapshot) = self.snapshots.pop_front() {
+            self.last = snapshot;
+        }
+        self.last.clone()
+    }
+}
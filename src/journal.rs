@@ -0,0 +1,184 @@
+//! A log of how breaks went, kept in `journal.log` next to the state file,
+//! one event per line: `<RFC 3339 time> <event> <key>=<value> ...`.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+
+use crate::state::State;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JournalEvent {
+    /// The session was unlocked `after` the break started, and the break
+    /// was let go.
+    BreakCutShort { after: Duration },
+    /// Always the last thing on its line, so it can have spaces.
+    EmergencyOverride { reason: String },
+    /// The app stopped while enforcing a break. Last on its line too.
+    EnforcementReleased { cause: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub at: DateTime<Utc>,
+    pub event: JournalEvent,
+}
+
+impl JournalEntry {
+    pub fn to_line(&self) -> String {
+        let event = match &self.event {
+            JournalEvent::BreakCutShort { after } => {
+                format!("break_cut_short after_secs={}", after.as_secs())
+            }
+            JournalEvent::EmergencyOverride { reason } => {
+                format!("emergency_override reason={}", one_line(reason))
+            }
+            JournalEvent::EnforcementReleased { cause } => {
+                format!("enforcement_released cause={}", one_line(cause))
+            }
+        };
+        format!("{} {event}", self.at.to_rfc3339())
+    }
+
+    pub fn parse(line: &str) -> Result<JournalEntry> {
+        let mut tokens = line.split_whitespace();
+        let at = tokens.next().context("missing time")?;
+        let at = DateTime::parse_from_rfc3339(at)
+            .with_context(|| format!("invalid time `{at}`"))?
+            .with_timezone(&Utc);
+        let event = match tokens.next().context("missing event")? {
+            "break_cut_short" => JournalEvent::BreakCutShort {
+                after: Duration::from_secs(value(tokens.next(), "after_secs")?),
+            },
+            "emergency_override" => JournalEvent::EmergencyOverride {
+                reason: rest_of_line(line, "reason")?,
+            },
+            "enforcement_released" => JournalEvent::EnforcementReleased {
+                cause: rest_of_line(line, "cause")?,
+            },
+            name => bail!("unknown event `{name}`"),
+        };
+        Ok(JournalEntry { at, event })
+    }
+}
+
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The text after `<key>=` in the third field onwards.
+fn rest_of_line(line: &str, key: &str) -> Result<String> {
+    let rest = line.splitn(3, ' ').nth(2).unwrap_or_default();
+    let Some(value) = rest.strip_prefix(key).and_then(|r| r.strip_prefix('=')) else {
+        bail!("expected `{key}=...`, got `{rest}`");
+    };
+    Ok(value.to_string())
+}
+
+fn value(token: Option<&str>, key: &str) -> Result<u64> {
+    let token = token.with_context(|| format!("missing {key}"))?;
+    let Some(value) = token.strip_prefix(key).and_then(|t| t.strip_prefix('=')) else {
+        bail!("expected `{key}=...`, got `{token}`");
+    };
+    value
+        .parse()
+        .with_context(|| format!("invalid {key} `{value}`"))
+}
+
+#[derive(Debug, Clone)]
+pub struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    pub fn new(path: PathBuf) -> Journal {
+        Journal { path }
+    }
+
+    /// The journal next to the state file, if there's a place for it.
+    pub fn open() -> Option<Journal> {
+        State::dir().map(|dir| Journal::new(dir.join("journal.log")))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn record(&self, event: JournalEvent) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to open {}", self.path.display()))?;
+        let entry = JournalEntry {
+            at: Utc::now(),
+            event,
+        };
+        writeln!(file, "{}", entry.to_line())?;
+        Ok(())
+    }
+
+    /// Every entry, oldest first. Nothing if there's no journal yet.
+    pub fn entries(&self) -> Result<Vec<JournalEntry>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = fs::read_to_string(&self.path)
+            .with_context(|| format!("failed to read {}", self.path.display()))?;
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                JournalEntry::parse(line)
+                    .with_context(|| format!("{}:{}", self.path.display(), i + 1))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn entry_round_trip() {
+        let entry = JournalEntry {
+            at: "2023-03-20T09:30:00Z".parse().unwrap(),
+            event: JournalEvent::BreakCutShort {
+                after: Duration::from_secs(95),
+            },
+        };
+        let line = entry.to_line();
+        assert_eq!(
+            line,
+            "2023-03-20T09:30:00+00:00 break_cut_short after_secs=95"
+        );
+        assert_eq!(JournalEntry::parse(&line).unwrap(), entry);
+        assert!(JournalEntry::parse("2023-03-20T09:30:00Z break_cut_short after=95").is_err());
+
+        let entry = JournalEntry {
+            event: JournalEvent::EmergencyOverride {
+                reason: "prod is down,\n again".to_string(),
+            },
+            ..entry
+        };
+        let line = entry.to_line();
+        assert!(line.ends_with("emergency_override reason=prod is down, again"));
+        assert_eq!(
+            JournalEntry::parse(&line).unwrap().event,
+            JournalEvent::EmergencyOverride {
+                reason: "prod is down, again".to_string()
+            }
+        );
+    }
+}
@@ -2,37 +2,91 @@
 // #![windows_subsystem = "windows"]
 
 use std::{
+    cell::RefCell,
     ops::ControlFlow,
+    rc::Rc,
     sync::mpsc,
     time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::{bail, Error, Result};
+use chrono::{DateTime, Utc};
+use clap::Parser;
 use device_query::{DeviceQuery, DeviceState};
 
 use activity_monitor::{ActivityKind, ActivityMonitor};
 use break_notifier::BreakState;
+use calendar::CalendarSource;
+use cli::{Cli, Command, ScheduleCommand};
+use config::Config;
+use deferral::{BreakDeferral, DeferDecision};
+use enforcement::{BreakPreference, EnforcementAction, SystemRunner};
+use input_block::InputBlocker;
+use journal::{Journal, JournalEvent};
+use preparation::Preparation;
+use schedule::Schedule;
+use state::{SavedBreak, State};
 use time::{Stopwatch, Timer};
 use tray_icon::{TrayInputEvent, TrayItem, TrayItemMode};
 use utils::*;
 
+mod activity_history;
 mod activity_monitor;
 mod break_notifier;
+mod calendar;
+mod calibrate;
+mod cli;
+mod config;
+mod deferral;
+mod emergency;
+mod enforcement;
+#[cfg(target_os = "linux")]
+mod evdev_grab;
+#[cfg(target_os = "linux")]
+mod evdev_input;
+mod input_block;
+mod input_source;
+mod journal;
+#[cfg(target_os = "linux")]
+mod mpris;
 mod notification;
+mod preparation;
+mod preview;
+mod safety;
+mod sampler;
 mod schedule;
+mod scoring;
+mod state;
 mod time;
+mod trace;
 mod tray_icon;
 mod utils;
+#[cfg(target_os = "linux")]
+mod watchdog;
+#[cfg(target_os = "linux")]
+mod x11;
+
+static SINCE_START: once_cell::sync::Lazy<SystemTime> = once_cell::sync::Lazy::new(SystemTime::now);
 
 pub struct World {
     delta: Duration,
     system_since_start: &'static SystemTime,
+    now: Instant,
+    wall_clock: DateTime<Utc>,
 }
 
 impl World {
     pub fn delta(&self) -> Duration {
         self.delta
     }
+
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    pub fn wall_clock(&self) -> DateTime<Utc> {
+        self.wall_clock
+    }
 }
 
 #[derive(Debug)]
@@ -52,31 +106,122 @@ impl AppState {
     }
 }
 
-enum BreakPreference {
-    Hibernate { let_user_prepare: bool },
-    Shutdown { let_user_prepare: bool },
-    BlockInput { let_user_prepare: bool },
-}
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let config = Config::load()?;
+    let schedule = Schedule::from_config(&config.schedule)?;
 
-impl BreakPreference {
-    pub fn get() -> BreakPreference {
-        todo!();
+    match cli.command {
+        None => run(config, schedule),
+        Some(Command::Schedule {
+            command: ScheduleCommand::Preview { days },
+        }) => preview::print_preview(&config, &schedule, Utc::now(), days),
+        Some(Command::RecordActivity { path, secs }) => {
+            trace::record(&path, &config.activity, secs.map(Duration::from_secs))
+        }
+        Some(Command::ReplayActivity {
+            path,
+            profile,
+            high_activity_level,
+            low_activity_level,
+            active_secs,
+            idle_secs,
+        }) => {
+            let mut activity = config.activity;
+            activity.profile = profile.unwrap_or(activity.profile);
+            activity.high_activity_level =
+                high_activity_level.unwrap_or(activity.high_activity_level);
+            activity.low_activity_level = low_activity_level.unwrap_or(activity.low_activity_level);
+            activity.active_secs = active_secs.unwrap_or(activity.active_secs);
+            activity.idle_secs = idle_secs.unwrap_or(activity.idle_secs);
+            trace::print_replay(&path, &activity)
+        }
+        Some(Command::Calibrate {
+            minutes,
+            trace,
+            write,
+        }) => calibrate::run(
+            &config.activity,
+            Duration::from_secs(minutes * 60),
+            trace.as_deref(),
+            write,
+        ),
+        #[cfg(target_os = "linux")]
+        Some(Command::Watchdog { timeout_secs }) => {
+            watchdog::run(Duration::from_secs(timeout_secs))
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(Command::Watchdog { .. }) => bail!("the watchdog is only supported on Linux"),
     }
 }
 
-fn main() -> Result<()> {
+fn run(config: Config, schedule: Schedule) -> Result<()> {
+    safety::install(Journal::open())?;
+    let _release_guard = safety::ReleaseGuard;
+    #[cfg(target_os = "linux")]
+    let mut watchdog = if config.watchdog.enabled {
+        Some(watchdog::Watchdog::spawn(config.watchdog.timeout())?)
+    } else {
+        None
+    };
     let (tray_item_sender, tray_item_receiver) = mpsc::sync_channel(10);
     let tray_item = TrayItem::new_with_sender(TrayItemMode::default(), &tray_item_sender)?;
 
-    let break_duration = Duration::from_secs(5);
-    let mut break_notifier = break_notifier::BasicTimeBreak::new(
-        BreakState::NotBreak,
-        break_duration,
-        Duration::from_secs(5),
-    );
+    let mut break_notifier = break_notifier::BasicTimeBreak::from_config(&config.breaks);
+
+    let mut deferral = BreakDeferral::from_config(&config.deferral);
+    if !config.calendar.paths.is_empty() {
+        deferral.add_source(CalendarSource::new(config.calendar.expanded_paths()));
+    }
+    #[cfg(target_os = "linux")]
+    if config.presentation.is_enabled() {
+        match x11::PresentationDetector::from_config(&config.presentation) {
+            Ok(detector) => deferral.add_source(detector),
+            Err(e) => eprintln!("presentation detection disabled: {e:#}"),
+        }
+    }
+    let mut media_pause: MediaPause = None;
+    #[cfg(target_os = "linux")]
+    if config.media.is_enabled() {
+        match mpris::MediaPlayers::connect() {
+            Ok(players) => {
+                if config.media.defers() {
+                    deferral.add_source(mpris::MediaSource::from_config(
+                        players.clone(),
+                        &config.media,
+                    ));
+                }
+                if config.media.pause_on_break {
+                    media_pause = Some(mpris::BreakMediaPause::new(players));
+                }
+            }
+            Err(e) => eprintln!("media player integration disabled: {e:#}"),
+        }
+    }
 
     let (break_send, break_recv) = mpsc::sync_channel(2);
     let mut app_state = AppState::NotBreak;
+    // Bad passthrough keys should fail here rather than at the first break.
+    let input_blocker = Rc::new(RefCell::new(InputBlocker::from_config(&config.blocking)?));
+    let mut enforcement: Option<Box<dyn EnforcementAction>> = None;
+    // Enforcement that starts once the user had time to prepare.
+    let mut preparing: Option<(Preparation, Box<dyn EnforcementAction>)> = None;
+
+    // Carry on with a break the app was stopped in the middle of, by the
+    // watchdog or otherwise. The countdown was already had.
+    let mut resuming = false;
+    match State::load() {
+        Ok(State {
+            current_break: Some(saved),
+            ..
+        }) if saved.remaining_at(Utc::now()).is_some() => {
+            break_notifier.resume_break(saved.elapsed_at(Utc::now()), saved.long);
+            break_send.just_send(true);
+            resuming = true;
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("failed to load the running break: {e:#}"),
+    }
 
     let break_send_c = break_send.clone();
     break_notifier.set_start_break_callback(Some(move || break_send_c.just_send(true)));
@@ -85,13 +230,57 @@ fn main() -> Result<()> {
     let preparation_time = Duration::from_secs(30);
 
     main_loop_run(|world| {
-        break_notifier.update(world);
+        if safety::exit_requested() {
+            if let Some(action) = &enforcement {
+                if let Some(journal) = Journal::open() {
+                    let _res = journal.record(JournalEvent::EnforcementReleased {
+                        cause: format!("signal while enforcing {}", action.name()),
+                    });
+                }
+            }
+            save_current_break(running_break(&break_notifier, world.wall_clock()));
+            return ControlFlow::Break(Ok(()));
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(watchdog) = watchdog.as_mut() {
+            watchdog.beat(enforcement.is_some());
+        }
+        let in_work_window = schedule.is_active_at(world.wall_clock());
+        if in_work_window {
+            let hold = if break_notifier.is_break_due(world) {
+                match deferral.update(world.wall_clock()) {
+                    Ok(DeferDecision::Proceed) => false,
+                    Ok(DeferDecision::Deferred(busy)) => {
+                        let _res = notification::notify(
+                            "Break postponed",
+                            &format!("{}. Your break starts once it's over.", busy.reason),
+                        );
+                        true
+                    }
+                    Ok(DeferDecision::Hold) => true,
+                    // Held until the sources are asked again.
+                    Err(e) => {
+                        eprintln!("{e:#}");
+                        true
+                    }
+                }
+            } else {
+                deferral.reset();
+                false
+            };
+            break_notifier.set_hold_break(hold);
+        }
+        // A break that already started runs to its end after the window closes.
+        if in_work_window || break_notifier.break_state() == BreakState::Break {
+            break_notifier.update(world);
+        }
 
         if let Some(tray_input_event) = tray_item_receiver.maybe_recv().break_res_err()? {
             match tray_input_event {
                 TrayInputEvent::Quit => return ControlFlow::Break(Ok(())),
                 TrayInputEvent::RestartWork => {
                     break_notifier.switch_to(BreakState::NotBreak);
+                    end_break(&mut enforcement, &mut preparing, &mut media_pause);
                     app_state = AppState::NotBreak;
                 }
                 TrayInputEvent::SkipWork { by } => {
@@ -104,44 +293,166 @@ fn main() -> Result<()> {
         if let Some(recv_is_break) = break_recv.maybe_recv().break_res_err()? {
             if recv_is_break {
                 app_state = AppState::Dialog;
-                let break_preference = BreakPreference::get();
-                match break_preference {
-                    BreakPreference::Hibernate { let_user_prepare } => todo!(),
-                    BreakPreference::Shutdown { let_user_prepare } => todo!(),
-                    BreakPreference::BlockInput { let_user_prepare } => if let_user_prepare {},
+                let break_preference = BreakPreference::get(config.break_profile());
+                end_enforcement(&mut enforcement);
+                if resuming && !break_preference.can_resume() {
+                    // Powering off again on every boot would never end, the
+                    // break counts as served.
+                    break_notifier.switch_to(BreakState::NotBreak);
+                    save_current_break(None);
+                    resuming = false;
+                    app_state = AppState::NotBreak;
+                    return ControlFlow::Continue(());
+                }
+                match break_preference.action(&config, &input_blocker, SystemRunner) {
+                    Ok(action) if break_preference.let_user_prepare() && !resuming => {
+                        preparing = Some((Preparation::new(preparation_time), action));
+                    }
+                    Ok(action) => enforcement = start_enforcement(action),
+                    Err(e) => eprintln!("failed to enforce the break: {e:#}"),
+                }
+                resuming = false;
+                save_current_break(running_break(&break_notifier, world.wall_clock()));
+                #[cfg(target_os = "linux")]
+                if let Some(media_pause) = media_pause.as_mut() {
+                    media_pause.break_started();
                 }
                 app_state = AppState::Break;
+            } else {
+                end_break(&mut enforcement, &mut preparing, &mut media_pause);
+                app_state = AppState::NotBreak;
+            }
+        }
+        if let Some((preparation, _)) = preparing.as_mut() {
+            if let Some(time_left) = preparation.update(world) {
+                let _res = notification::notify_countdown(time_left, preparation.ready_flag());
+            }
+            if preparation.is_over() {
+                if let Some((_, action)) = preparing.take() {
+                    enforcement = start_enforcement(action);
+                }
+            }
+        }
+        if let Some(action) = enforcement.as_mut() {
+            if let Err(e) = action.update() {
+                eprintln!("failed to {}: {e:#}", action.name());
+            }
+            let overridden = action.is_overridden();
+            if action.is_cut_short() || overridden {
+                break_notifier.switch_to(BreakState::NotBreak);
+                end_break(&mut enforcement, &mut preparing, &mut media_pause);
+                app_state = AppState::NotBreak;
+            }
+            if overridden {
+                // The last beat said enforcing, and the dialog blocks the loop.
+                #[cfg(target_os = "linux")]
+                if let Some(watchdog) = watchdog.as_mut() {
+                    watchdog.idle();
+                }
+                let reason = emergency::ask_reason();
+                if let Some(journal) = Journal::open() {
+                    if let Err(e) = journal.record(JournalEvent::EmergencyOverride { reason }) {
+                        eprintln!("failed to record the override: {e:#}");
+                    }
+                }
             }
         }
-        let _res = block_input(app_state.is_break());
         ControlFlow::Continue(())
     })?;
 
     Ok(())
 }
 
+/// Media paused for the break. Nothing is paused off Linux.
+#[cfg(target_os = "linux")]
+type MediaPause = Option<mpris::BreakMediaPause>;
+#[cfg(not(target_os = "linux"))]
+type MediaPause = Option<()>;
+
+/// Everything that has to happen however a break ends, on its own or early.
+/// `switch_to` doesn't call the end callback, so early ends come here too.
+fn end_break(
+    enforcement: &mut Option<Box<dyn EnforcementAction>>,
+    preparing: &mut Option<(Preparation, Box<dyn EnforcementAction>)>,
+    media_pause: &mut MediaPause,
+) {
+    *preparing = None;
+    end_enforcement(enforcement);
+    #[cfg(target_os = "linux")]
+    if let Some(media_pause) = media_pause.as_mut() {
+        media_pause.break_ended();
+    }
+    save_current_break(None);
+}
+
+fn running_break(
+    break_notifier: &break_notifier::BasicTimeBreak,
+    now: DateTime<Utc>,
+) -> Option<SavedBreak> {
+    if break_notifier.break_state() != BreakState::Break {
+        return None;
+    }
+    let duration = break_notifier.current_break_duration();
+    let elapsed = duration.saturating_sub(break_notifier.time_before_end_break()?);
+    Some(SavedBreak {
+        started_at: now - chrono::Duration::from_std(elapsed).ok()?,
+        long: break_notifier.is_long_break(),
+        duration_secs: duration.as_secs(),
+    })
+}
+
+/// Remembers the running break, or that there isn't one, for when the app
+/// is restarted.
+fn save_current_break(current_break: Option<SavedBreak>) {
+    let result = State::load().and_then(|mut state| {
+        state.current_break = current_break;
+        state.save()
+    });
+    if let Err(e) = result {
+        eprintln!("failed to save the running break: {e:#}");
+    }
+}
+
+fn start_enforcement(mut action: Box<dyn EnforcementAction>) -> Option<Box<dyn EnforcementAction>> {
+    match action.start() {
+        Ok(()) => Some(action),
+        Err(e) => {
+            eprintln!("failed to {}: {e:#}", action.name());
+            None
+        }
+    }
+}
+
+fn end_enforcement(enforcement: &mut Option<Box<dyn EnforcementAction>>) {
+    if let Some(mut action) = enforcement.take() {
+        if let Err(e) = action.end() {
+            eprintln!(
+                "failed to stop enforcing the break ({}): {e:#}",
+                action.name()
+            );
+        }
+    }
+}
+
 pub fn main_loop_run<F, B>(mut f: F) -> B
 where
     F: FnMut(&World) -> ControlFlow<B, ()>,
 {
-    static SINCE_START: once_cell::sync::Lazy<SystemTime> =
-        once_cell::sync::Lazy::new(SystemTime::now);
-
     let mut world = World {
         delta: Duration::ZERO,
         system_since_start: &SINCE_START,
+        now: Instant::now(),
+        wall_clock: Utc::now(),
     };
     let mut last_frame_time = Instant::now();
     loop {
         world.delta = last_frame_time.elapsed();
+        world.wall_clock = Utc::now();
         last_frame_time = Instant::now();
+        world.now = last_frame_time;
 
         if let ControlFlow::Break(b) = f(&world) {
             break b;
         };
     }
 }
-
-pub fn block_input(block: bool) -> std::result::Result<(), windows::core::Error> {
-    unsafe { windows::Win32::UI::Input::KeyboardAndMouse::BlockInput(block).ok() }
-}
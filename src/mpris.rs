@@ -0,0 +1,211 @@
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use zbus::blocking::{fdo::DBusProxy, Connection, Proxy};
+
+use crate::{
+    config::MediaConfig,
+    deferral::{Busy, BusySource},
+    x11::PresentationDetector,
+};
+
+const BUS_NAME_PREFIX: &str = "org.mpris.MediaPlayer2.";
+const OBJECT_PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER_INTERFACE: &str = "org.mpris.MediaPlayer2.Player";
+
+/// Media players on the session bus.
+#[derive(Clone)]
+pub struct MediaPlayers {
+    connection: Connection,
+}
+
+impl MediaPlayers {
+    pub fn connect() -> Result<MediaPlayers> {
+        Ok(MediaPlayers {
+            connection: Connection::session()?,
+        })
+    }
+
+    /// Bus names of every player, like `org.mpris.MediaPlayer2.vlc`.
+    pub fn players(&self) -> Result<Vec<String>> {
+        let names = DBusProxy::new(&self.connection)?.list_names()?;
+        Ok(names
+            .into_iter()
+            .map(|name| name.to_string())
+            .filter(|name| name.starts_with(BUS_NAME_PREFIX))
+            .collect())
+    }
+
+    fn player(&self, bus_name: &str) -> Result<Proxy<'_>> {
+        Ok(Proxy::new(
+            &self.connection,
+            bus_name.to_string(),
+            OBJECT_PATH,
+            PLAYER_INTERFACE,
+        )?)
+    }
+
+    pub fn is_playing(&self, bus_name: &str) -> Result<bool> {
+        let status: String = self.player(bus_name)?.get_property("PlaybackStatus")?;
+        Ok(status == "Playing")
+    }
+
+    pub fn playing(&self) -> Result<Vec<String>> {
+        Ok(self
+            .players()?
+            .into_iter()
+            .filter(|name| self.is_playing(name).unwrap_or(false))
+            .collect())
+    }
+
+    /// Pauses everything that's playing. A player that fails to pause
+    /// doesn't keep the rest from pausing.
+    pub fn pause_all(&self) -> Result<Paused> {
+        let mut paused = Paused::default();
+        for name in self.playing()? {
+            match self
+                .player(&name)
+                .and_then(|player| Ok(player.call_method("Pause", &())?))
+            {
+                Ok(_) => paused.players.push(name),
+                Err(e) => paused
+                    .errors
+                    .push(e.context(format!("failed to pause {name}"))),
+            }
+        }
+        Ok(paused)
+    }
+
+    /// Resumes each of `bus_names`, returning why the ones that didn't
+    /// resume failed. Players that exited in the meantime are among those.
+    pub fn play(&self, bus_names: &[String]) -> Vec<anyhow::Error> {
+        bus_names
+            .iter()
+            .filter_map(|name| {
+                self.player(name)
+                    .and_then(|player| Ok(player.call_method("Play", &())?))
+                    .err()
+                    .map(|e| e.context(format!("failed to resume {name}")))
+            })
+            .collect()
+    }
+}
+
+/// What [`MediaPlayers::pause_all`] got done.
+#[derive(Debug, Default)]
+pub struct Paused {
+    pub players: Vec<String>,
+    pub errors: Vec<anyhow::Error>,
+}
+
+/// Pauses media when a break starts and resumes it once the break is over.
+pub struct BreakMediaPause {
+    players: MediaPlayers,
+    paused: Vec<String>,
+}
+
+impl BreakMediaPause {
+    pub fn new(players: MediaPlayers) -> BreakMediaPause {
+        BreakMediaPause {
+            players,
+            paused: Vec::new(),
+        }
+    }
+
+    pub fn break_started(&mut self) {
+        match self.players.pause_all() {
+            Ok(paused) => {
+                for e in paused.errors {
+                    eprintln!("{e:#}");
+                }
+                self.paused = paused.players;
+            }
+            Err(e) => eprintln!("failed to pause media: {e:#}"),
+        }
+    }
+
+    pub fn break_ended(&mut self) {
+        let paused = std::mem::take(&mut self.paused);
+        for e in self.players.play(&paused) {
+            eprintln!("{e:#}");
+        }
+    }
+}
+
+/// Whether a player's bus name belongs to one of the configured players.
+/// Browsers and the like add an instance suffix, `firefox.instance1234`.
+fn player_matches(bus_name: &str, configured: &str) -> bool {
+    let Some(name) = bus_name.strip_prefix(BUS_NAME_PREFIX) else {
+        return false;
+    };
+    let name = name.to_lowercase();
+    let configured = configured.to_lowercase();
+    name == configured || name.starts_with(&format!("{configured}."))
+}
+
+/// Busy while a configured player is playing, or while anything plays with a
+/// fullscreen window in front.
+pub struct MediaSource {
+    players: MediaPlayers,
+    configured: Vec<String>,
+    fullscreen: Option<PresentationDetector>,
+}
+
+impl MediaSource {
+    pub fn from_config(players: MediaPlayers, config: &MediaConfig) -> MediaSource {
+        let fullscreen = if config.defer_while_fullscreen_video {
+            PresentationDetector::connect(true, Vec::new())
+                .map_err(|e| eprintln!("fullscreen video detection disabled: {e:#}"))
+                .ok()
+        } else {
+            None
+        };
+        MediaSource {
+            players,
+            configured: config.players.clone(),
+            fullscreen,
+        }
+    }
+}
+
+impl BusySource for MediaSource {
+    fn busy_at(&mut self, now: DateTime<Utc>) -> Result<Option<Busy>> {
+        let Ok(playing) = self.players.playing() else {
+            return Ok(None);
+        };
+        if let Some(name) = playing.iter().find(|name| {
+            self.configured
+                .iter()
+                .any(|configured| player_matches(name, configured))
+        }) {
+            return Ok(Some(Busy {
+                reason: format!("{} is playing", name.trim_start_matches(BUS_NAME_PREFIX)),
+                until: None,
+            }));
+        }
+        if playing.is_empty() {
+            return Ok(None);
+        }
+        let Some(fullscreen) = self.fullscreen.as_mut() else {
+            return Ok(None);
+        };
+        Ok(fullscreen.busy_at(now)?.map(|_| Busy {
+            reason: "A video is playing in fullscreen".to_string(),
+            until: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn matches_player_instances() {
+        assert!(player_matches("org.mpris.MediaPlayer2.vlc", "VLC"));
+        assert!(player_matches(
+            "org.mpris.MediaPlayer2.firefox.instance1234",
+            "firefox"
+        ));
+        assert!(!player_matches("org.mpris.MediaPlayer2.vlcx", "vlc"));
+    }
+}
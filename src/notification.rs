@@ -1,3 +1,13 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::time::FormattedDuration;
+
 pub fn notify(summary: &str, body: &str) -> notify_rust::error::Result<()> {
     notify_rust::Notification::new()
         .appname("Pomodoro SS")
@@ -7,3 +17,32 @@ pub fn notify(summary: &str, body: &str) -> notify_rust::error::Result<()> {
         .show()?;
     Ok(())
 }
+
+/// Tells how long until the break is enforced, with an action to start it
+/// right away that sets `ready`. Actions are only supported on Linux.
+pub fn notify_countdown(
+    time_left: Duration,
+    ready: Arc<AtomicBool>,
+) -> notify_rust::error::Result<()> {
+    let mut notification = notify_rust::Notification::new();
+    notification
+        .appname("Pomodoro SS")
+        .auto_icon()
+        // Rounded rather than truncated, 29.98s is still "30s".
+        .summary(&format!(
+            "Break in {}",
+            FormattedDuration::new(time_left + Duration::from_millis(500))
+        ))
+        .body("Save your work and wrap up.")
+        .action("ready", "I'm ready, start now");
+    let handle = notification.show()?;
+    #[cfg(target_os = "linux")]
+    std::thread::spawn(move || {
+        handle.wait_for_action(|action| {
+            if action == "ready" {
+                ready.store(true, Ordering::Relaxed);
+            }
+        })
+    });
+    Ok(())
+}
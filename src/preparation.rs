@@ -0,0 +1,93 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::{time::Timer, World};
+
+/// How long before enforcement starts the user is told about it.
+pub const NOTICES: [Duration; 3] = [
+    Duration::from_secs(30),
+    Duration::from_secs(10),
+    Duration::from_secs(3),
+];
+
+/// A grace period before a break is enforced, to save work and wrap up. It's
+/// over when the time is up or the user says they're ready.
+pub struct Preparation {
+    timer: Timer,
+    /// Still to come, longest first.
+    notices: Vec<Duration>,
+    ready: Arc<AtomicBool>,
+}
+
+impl Preparation {
+    pub fn new(duration: Duration) -> Preparation {
+        Preparation {
+            timer: Timer::new(duration),
+            notices: NOTICES.to_vec(),
+            ready: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Setting it ends the preparation on the next update, from any thread.
+    pub fn ready_flag(&self) -> Arc<AtomicBool> {
+        self.ready.clone()
+    }
+
+    pub fn time_left(&self) -> Duration {
+        self.timer.time_left()
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.ready.load(Ordering::Relaxed) || self.timer.time_left().is_zero()
+    }
+
+    /// The notice that's due, if one is. Notices that were skipped over, like
+    /// those longer than the whole preparation, collapse into one showing
+    /// the actual time left.
+    pub fn update(&mut self, world: &World) -> Option<Duration> {
+        self.timer.update(world);
+        if self.is_over() {
+            return None;
+        }
+        let time_left = self.timer.time_left();
+        let due = self.notices.iter().filter(|n| **n >= time_left).count();
+        if due == 0 {
+            return None;
+        }
+        self.notices.drain(..due);
+        Some(time_left)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::time::SimulatedClock;
+    use chrono::Utc;
+
+    #[test]
+    fn notices_and_ready() {
+        let mut clock = SimulatedClock::new(Utc::now(), Duration::from_secs(1));
+        let mut preparation = Preparation::new(Duration::from_secs(30));
+        let mut notices = Vec::new();
+        let mut world = clock.advance(Duration::ZERO);
+        while !preparation.is_over() {
+            if let Some(time_left) = preparation.update(&world) {
+                notices.push(time_left.as_secs());
+            }
+            world = clock.tick();
+        }
+        assert_eq!(notices, vec![30, 10, 3]);
+
+        let mut preparation = Preparation::new(Duration::from_secs(30));
+        preparation.update(&clock.tick());
+        preparation.ready_flag().store(true, Ordering::Relaxed);
+        assert!(preparation.is_over());
+        assert_eq!(preparation.update(&clock.tick()), None);
+    }
+}
@@ -0,0 +1,197 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+
+use crate::{
+    break_notifier::{BasicTimeBreak, BreakState},
+    config::{BreakConfig, Config},
+    schedule::Schedule,
+    time::{FormattedDuration, SimulatedClock},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PreviewEvent {
+    WindowOpens,
+    WindowCloses,
+    BreakStarts { duration: Duration, long: bool },
+    BreakEnds,
+}
+
+/// Runs the break policy against the schedule the same way the main loop
+/// does, one simulated second at a time. Deferral isn't simulated, breaks
+/// start on time as if nothing ever kept the user busy.
+pub fn simulate(
+    breaks: &BreakConfig,
+    schedule: &Schedule,
+    start: DateTime<Utc>,
+    days: u32,
+) -> Vec<(DateTime<Utc>, PreviewEvent)> {
+    let mut break_notifier = BasicTimeBreak::from_config(breaks);
+    let mut clock = SimulatedClock::new(start, Duration::from_secs(1));
+    let end = start + chrono::Duration::days(days as i64);
+    let mut events = Vec::new();
+
+    let mut active = false;
+    let mut next_change = start;
+    while clock.wall_clock() < end {
+        let now = clock.wall_clock();
+        // Only look the schedule up again when a window could have opened
+        // or closed.
+        if now >= next_change {
+            let now_active = schedule.is_active_at(now);
+            if now_active != active && !schedule.is_unrestricted() {
+                let event = if now_active {
+                    PreviewEvent::WindowOpens
+                } else {
+                    PreviewEvent::WindowCloses
+                };
+                events.push((now, event));
+            }
+            active = now_active;
+            next_change = match schedule.window_at(now) {
+                Some((_, window_end)) => window_end,
+                None => schedule.next_window_start(now).unwrap_or(end),
+            };
+        }
+
+        let world = clock.tick();
+        // A break that already started runs to its end after the window closes.
+        if !active && break_notifier.break_state() != BreakState::Break {
+            continue;
+        }
+        let before = break_notifier.break_state();
+        break_notifier.update(&world);
+        match (before, break_notifier.break_state()) {
+            (BreakState::NotBreak, BreakState::Break) => events.push((
+                world.wall_clock(),
+                PreviewEvent::BreakStarts {
+                    duration: break_notifier.current_break_duration(),
+                    long: break_notifier.is_long_break(),
+                },
+            )),
+            (BreakState::Break, BreakState::NotBreak) => {
+                events.push((world.wall_clock(), PreviewEvent::BreakEnds))
+            }
+            _ => {}
+        }
+    }
+    events
+}
+
+pub fn print_preview(
+    config: &Config,
+    schedule: &Schedule,
+    start: DateTime<Utc>,
+    days: u32,
+) -> Result<()> {
+    let time_zone = schedule.time_zone();
+    const FORMAT: &str = "%a %Y-%m-%d %H:%M %Z";
+    println!(
+        "Simulating {days} day(s) from {}",
+        time_zone.format(start, FORMAT)
+    );
+    if schedule.is_unrestricted() {
+        println!("No work windows configured, breaks run around the clock");
+    }
+    for (at, event) in simulate(&config.breaks, schedule, start, days) {
+        let description = match event {
+            PreviewEvent::WindowOpens => "work window opens".to_string(),
+            PreviewEvent::WindowCloses => "work window closes".to_string(),
+            PreviewEvent::BreakStarts { duration, long } => format!(
+                "{}break for {}",
+                if long { "long " } else { "" },
+                FormattedDuration::new(duration)
+            ),
+            PreviewEvent::BreakEnds => "break ends".to_string(),
+        };
+        println!("{}  {description}", time_zone.format(at, FORMAT));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::schedule::{Day, ScheduleTimeZone, WorkWindow};
+    use chrono::NaiveTime;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn preview_follows_schedule_and_long_breaks() {
+        let mut schedule = Schedule::new().with_time_zone(ScheduleTimeZone::Iana(chrono_tz::UTC));
+        schedule.add_window(WorkWindow::new(
+            Day::WEEKDAYS,
+            NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(11, 0, 0).unwrap(),
+        ));
+        let breaks = BreakConfig {
+            work_secs: 25 * 60,
+            break_secs: 5 * 60,
+            long_break_secs: 15 * 60,
+            long_break_every: 2,
+            ..Default::default()
+        };
+        let short = PreviewEvent::BreakStarts {
+            duration: Duration::from_secs(5 * 60),
+            long: false,
+        };
+        let long = PreviewEvent::BreakStarts {
+            duration: Duration::from_secs(15 * 60),
+            long: true,
+        };
+
+        // Monday
+        let events = simulate(&breaks, &schedule, utc("2023-06-05T08:00:00Z"), 1);
+        assert_eq!(
+            events,
+            vec![
+                (utc("2023-06-05T09:00:00Z"), PreviewEvent::WindowOpens),
+                (utc("2023-06-05T09:25:00Z"), short.clone()),
+                (utc("2023-06-05T09:30:00Z"), PreviewEvent::BreakEnds),
+                (utc("2023-06-05T09:55:00Z"), long),
+                (utc("2023-06-05T10:10:00Z"), PreviewEvent::BreakEnds),
+                (utc("2023-06-05T10:35:00Z"), short),
+                (utc("2023-06-05T10:40:00Z"), PreviewEvent::BreakEnds),
+                (utc("2023-06-05T11:00:00Z"), PreviewEvent::WindowCloses),
+            ]
+        );
+    }
+
+    #[test]
+    fn started_break_outlasts_window() {
+        let mut schedule = Schedule::new().with_time_zone(ScheduleTimeZone::Iana(chrono_tz::UTC));
+        schedule.add_window(WorkWindow::new(
+            Day::WEEKDAYS,
+            NaiveTime::from_hms_opt(9, 0, 0).unwrap(),
+            NaiveTime::from_hms_opt(9, 27, 0).unwrap(),
+        ));
+        let breaks = BreakConfig {
+            work_secs: 25 * 60,
+            break_secs: 5 * 60,
+            long_break_secs: 15 * 60,
+            long_break_every: 0,
+            ..Default::default()
+        };
+
+        let events = simulate(&breaks, &schedule, utc("2023-06-05T08:00:00Z"), 1);
+        assert_eq!(
+            events,
+            vec![
+                (utc("2023-06-05T09:00:00Z"), PreviewEvent::WindowOpens),
+                (
+                    utc("2023-06-05T09:25:00Z"),
+                    PreviewEvent::BreakStarts {
+                        duration: Duration::from_secs(5 * 60),
+                        long: false,
+                    }
+                ),
+                (utc("2023-06-05T09:27:00Z"), PreviewEvent::WindowCloses),
+                (utc("2023-06-05T09:30:00Z"), PreviewEvent::BreakEnds),
+            ]
+        );
+    }
+}
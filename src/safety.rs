@@ -0,0 +1,225 @@
+//! Makes sure blocked input is let go of however the app ends: returning,
+//! panicking or being asked to stop by a signal.
+//!
+//! Whatever needs undoing registers a release with [`on_release`]. Releases
+//! run from the panic hook before anything unwinds, when a [`ReleaseGuard`]
+//! drops, and on Windows straight from the console control handler. Linux
+//! signals only ask the main loop to stop, see [`exit_requested`], and a
+//! second one kills the process, which makes the kernel let go of grabs.
+
+use std::{
+    fmt, panic,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex, Once,
+    },
+};
+
+use anyhow::Result;
+
+use crate::journal::{Journal, JournalEvent};
+
+type Releaser = Box<dyn FnMut() + Send>;
+
+static RELEASERS: Mutex<Vec<(u64, Releaser)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static EXIT_REQUESTED: AtomicBool = AtomicBool::new(false);
+static INSTALL: Once = Once::new();
+
+/// Unregisters the release when dropped, without running it. Whoever owns
+/// this undoes things the usual way.
+pub struct Release {
+    id: u64,
+}
+
+impl Drop for Release {
+    fn drop(&mut self) {
+        let mut releasers = RELEASERS.lock().unwrap_or_else(|e| e.into_inner());
+        releasers.retain(|(id, _)| *id != self.id);
+    }
+}
+
+pub fn on_release<F>(release: F) -> Release
+where
+    F: FnMut() + Send + 'static,
+{
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut releasers = RELEASERS.lock().unwrap_or_else(|e| e.into_inner());
+    releasers.push((id, Box::new(release)));
+    Release { id }
+}
+
+/// Runs and forgets every registered release, returning how many ran.
+pub fn release_all() -> usize {
+    // Don't deadlock if the panic happened while registering.
+    let releasers = match RELEASERS.try_lock() {
+        Ok(mut releasers) => std::mem::take(&mut *releasers),
+        Err(std::sync::TryLockError::Poisoned(e)) => std::mem::take(&mut *e.into_inner()),
+        Err(std::sync::TryLockError::WouldBlock) => return 0,
+    };
+    let count = releasers.len();
+    for (_, mut release) in releasers {
+        release();
+    }
+    count
+}
+
+/// Runs every release when dropped, unwinding included.
+pub struct ReleaseGuard;
+
+impl Drop for ReleaseGuard {
+    fn drop(&mut self) {
+        release_all();
+    }
+}
+
+/// A signal asked the app to stop. The main loop should return so
+/// everything drops normally.
+pub fn exit_requested() -> bool {
+    EXIT_REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Installs the panic hook and signal handlers. Panics that had anything to
+/// release are noted in `journal`. Only the first call does anything.
+pub fn install(journal: Option<Journal>) -> Result<()> {
+    let mut result = Ok(());
+    INSTALL.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            release_on_panic(journal.as_ref(), info);
+            previous(info);
+        }));
+        result = install_signal_handlers();
+    });
+    result
+}
+
+/// What the panic hook does before handing over to the previous one.
+fn release_on_panic(journal: Option<&Journal>, panic: &dyn fmt::Display) {
+    if release_all() > 0 {
+        if let Some(journal) = journal {
+            let _res = journal.record(JournalEvent::EnforcementReleased {
+                cause: format!("panic: {panic}"),
+            });
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn install_signal_handlers() -> Result<()> {
+    extern "C" fn handle(_signal: libc::c_int) {
+        EXIT_REQUESTED.store(true, Ordering::Relaxed);
+    }
+
+    for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP] {
+        // SAFETY: The handler only stores to an atomic, which is async signal
+        // safe. `SA_RESETHAND` makes a second signal kill the process in
+        // case the main loop is stuck.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESETHAND;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn install_signal_handlers() -> Result<()> {
+    Ok(())
+}
+
+#[cfg(windows)]
+fn install_signal_handlers() -> Result<()> {
+    use windows::Win32::{Foundation::BOOL, System::Console::SetConsoleCtrlHandler};
+
+    // Runs on its own thread, and the process may be gone once it returns.
+    unsafe extern "system" fn handle(_ctrl_type: u32) -> BOOL {
+        EXIT_REQUESTED.store(true, Ordering::Relaxed);
+        release_all();
+        BOOL(1)
+    }
+
+    unsafe { SetConsoleCtrlHandler(Some(handle), true).ok()? };
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::main_loop_run;
+    use std::{
+        ops::ControlFlow,
+        sync::{atomic::AtomicUsize, Arc},
+    };
+
+    #[test]
+    fn panics_during_a_break_release() {
+        let released = Arc::new(AtomicUsize::new(0));
+        let counter = || {
+            let released = released.clone();
+            move || {
+                released.fetch_add(1, Ordering::Relaxed);
+            }
+        };
+        let path =
+            std::env::temp_dir().join(format!("pomodoro-ss-panic-{}.log", std::process::id()));
+        let journal = Journal::new(path.clone());
+
+        // What the hook does, without installing it in the test binary.
+        let release = on_release(counter());
+        release_on_panic(Some(&journal), &"injected during a break");
+        assert_eq!(released.load(Ordering::Relaxed), 1);
+        drop(release);
+        // Nothing to release, nothing to note.
+        release_on_panic(Some(&journal), &"injected between breaks");
+        assert_eq!(
+            journal
+                .entries()
+                .unwrap()
+                .into_iter()
+                .map(|entry| entry.event)
+                .collect::<Vec<_>>(),
+            vec![JournalEvent::EnforcementReleased {
+                cause: "panic: injected during a break".to_string()
+            }]
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        // Dropping the owner normally doesn't release.
+        drop(on_release(counter()));
+        assert_eq!(released.load(Ordering::Relaxed), 1);
+
+        // The guard releases whatever is still registered while unwinding.
+        let release = on_release(counter());
+        let result = panic::catch_unwind(|| {
+            let _release_guard = ReleaseGuard;
+            let mut frames = 0;
+            main_loop_run(|_| {
+                frames += 1;
+                if frames == 3 {
+                    panic!("injected during a break");
+                }
+                ControlFlow::<()>::Continue(())
+            })
+        });
+        assert!(result.is_err());
+        assert_eq!(released.load(Ordering::Relaxed), 2);
+        drop(release);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn signals_request_exit() {
+        install_signal_handlers().unwrap();
+        assert!(!exit_requested());
+        unsafe { libc::raise(libc::SIGTERM) };
+        assert!(exit_requested());
+        // The flag is global, so other tests would see it otherwise.
+        EXIT_REQUESTED.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,350 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use device_query::Keycode;
+
+use crate::{
+    activity_monitor::{is_modifier, ActivityKind, KeyClass},
+    config::InputBackend,
+    input_source::{self, InputSource},
+};
+
+/// Works out what activities happened between two snapshots of an
+/// [`InputSource`].
+pub struct ActivitySampler {
+    input_source: Box<dyn InputSource>,
+    previous_mouse_coord: (i32, i32),
+    previous_key_presses: Vec<Keycode>,
+    previous_mouse_pressses: Vec<bool>,
+}
+
+impl ActivitySampler {
+    pub fn new<S>(mut input_source: S) -> ActivitySampler
+    where
+        S: InputSource + 'static,
+    {
+        let snapshot = input_source.sample();
+        ActivitySampler {
+            input_source: Box::new(input_source),
+            previous_mouse_coord: snapshot.mouse_coord,
+            previous_key_presses: snapshot.keys,
+            previous_mouse_pressses: snapshot.mouse_buttons,
+        }
+    }
+
+    /// Samples the input source and works out what activities happened since
+    /// the previous sample, without scoring them.
+    pub fn sample(&mut self) -> Vec<(ActivityKind, usize)> {
+        let snapshot = self.input_source.sample();
+        let mouse_coord = snapshot.mouse_coord;
+        let keys = snapshot.keys;
+        let mouse_diff = {
+            let (px, py) = self.previous_mouse_coord;
+            let (nx, ny) = mouse_coord;
+            let (dx, dy) = ((px - nx) as f64, (py - ny) as f64);
+            (dx * dx + dy * dy).sqrt().abs()
+        };
+        let mouse_buttons_pressed = snapshot.mouse_buttons.iter().filter(|v| **v).count();
+        let mouse_buttons_just_pressed = self
+            .previous_mouse_pressses
+            .iter()
+            .zip(snapshot.mouse_buttons.iter())
+            .filter(|(&previous, &new)| !previous && new)
+            .count();
+        let (modifiers, other_keys): (Vec<_>, Vec<_>) = keys.iter().partition(|k| is_modifier(k));
+        let is_new = |k: &&&Keycode| !self.previous_key_presses.contains(k);
+        let keys_pressed = other_keys.len();
+        let modifiers_pressed = modifiers.len();
+        let modifiers_just_pressed = modifiers.iter().filter(is_new).count();
+        // Shift only changes what's typed.
+        let shortcut_modifier_held = modifiers
+            .iter()
+            .any(|k| !matches!(k, Keycode::LShift | Keycode::RShift));
+        let mut keys_just_pressed = [
+            (KeyClass::Typing, 0),
+            (KeyClass::Shortcut, 0),
+            (KeyClass::Navigation, 0),
+        ];
+        for key in other_keys.iter().filter(is_new) {
+            let class = KeyClass::of(**key, shortcut_modifier_held);
+            if let Some((_, count)) = keys_just_pressed.iter_mut().find(|(c, _)| *c == class) {
+                *count += 1;
+            }
+        }
+
+        let mut activities = Vec::new();
+        if mouse_buttons_pressed > 0 {
+            activities.push((ActivityKind::MousePressed, mouse_buttons_pressed));
+        }
+        if mouse_buttons_just_pressed > 0 {
+            activities.push((ActivityKind::MouseJustPressed, mouse_buttons_just_pressed));
+        }
+        if mouse_diff > 0.0 {
+            let activity = if mouse_buttons_pressed > 0 {
+                ActivityKind::MouseDrag {
+                    distance: mouse_diff,
+                }
+            } else {
+                ActivityKind::MouseMove {
+                    distance: mouse_diff,
+                }
+            };
+            activities.push((activity, 1));
+        }
+        let scroll_steps =
+            (snapshot.scroll.0.unsigned_abs() + snapshot.scroll.1.unsigned_abs()) as usize;
+        if scroll_steps > 0 {
+            activities.push((ActivityKind::Scroll, scroll_steps));
+        }
+        if keys_pressed > 0 {
+            activities.push((ActivityKind::KeyPress, keys_pressed));
+        }
+        for (class, count) in keys_just_pressed {
+            if count > 0 {
+                activities.push((ActivityKind::KeyJustPress { class }, count));
+            }
+        }
+        if modifiers_pressed > 0 {
+            activities.push((ActivityKind::ModifierPress, modifiers_pressed));
+        }
+        if modifiers_just_pressed > 0 {
+            activities.push((ActivityKind::ModifierJustPress, modifiers_just_pressed));
+        }
+
+        self.previous_mouse_pressses = snapshot.mouse_buttons;
+        self.previous_mouse_coord = mouse_coord;
+        self.previous_key_presses = keys;
+        activities
+    }
+}
+
+/// The activities of one sample, taken `interval` after the previous one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub at: Instant,
+    pub interval: Duration,
+    pub activities: Vec<(ActivityKind, usize)>,
+}
+
+/// Samples input on its own thread at a fixed rate, so scores don't depend on
+/// how fast whoever consumes them runs, and broadcasts every sample with
+/// activity to all subscribers. Cloning shares the thread, which stops once
+/// every clone and subscription is dropped.
+#[derive(Clone)]
+pub struct SamplerThread {
+    shared: Arc<Shared>,
+    handle: Arc<ThreadHandle>,
+}
+
+struct Shared {
+    interval: Duration,
+    running: AtomicBool,
+    /// `None` once the thread is gone, which disconnects every subscriber.
+    subscribers: Mutex<Option<Vec<mpsc::SyncSender<Sample>>>>,
+}
+
+/// Hangs up on the subscribers when the sampling thread ends, panics
+/// included, so they don't wait for samples that never come.
+struct HangUp(Arc<Shared>);
+
+impl Drop for HangUp {
+    fn drop(&mut self) {
+        let mut subscribers = self.0.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        *subscribers = None;
+    }
+}
+
+struct ThreadHandle {
+    shared: Arc<Shared>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for ThreadHandle {
+    fn drop(&mut self) {
+        self.shared.running.store(false, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _res = handle.join();
+        }
+    }
+}
+
+pub const SUBSCRIPTION_BUFFER: usize = 256;
+
+/// Samples from a [`SamplerThread`]. A subscriber that falls more than
+/// [`SUBSCRIPTION_BUFFER`] samples behind misses samples rather than holding
+/// up the others.
+pub struct Subscription {
+    samples: mpsc::Receiver<Sample>,
+    interval: Duration,
+    _handle: Arc<ThreadHandle>,
+}
+
+impl SamplerThread {
+    pub fn spawn(backend: InputBackend, rate_hz: u32) -> SamplerThread {
+        SamplerThread::spawn_with(move || input_source::open(backend), rate_hz)
+    }
+
+    /// `open` runs on the sampling thread, input sources don't have to be
+    /// `Send`. If it fails, nothing is sampled.
+    pub fn spawn_with<O, S>(open: O, rate_hz: u32) -> SamplerThread
+    where
+        O: FnOnce() -> anyhow::Result<S> + Send + 'static,
+        S: InputSource + 'static,
+    {
+        let interval = Duration::from_secs(1) / rate_hz.max(1);
+        let shared = Arc::new(Shared {
+            interval,
+            running: AtomicBool::new(true),
+            subscribers: Mutex::new(Some(Vec::new())),
+        });
+        let shared_c = shared.clone();
+        let handle = thread::spawn(move || {
+            let _hang_up = HangUp(shared_c.clone());
+            let mut sampler = match open() {
+                Ok(input_source) => ActivitySampler::new(input_source),
+                Err(e) => {
+                    eprintln!("failed to open input: {e:#}");
+                    return;
+                }
+            };
+            let mut next = Instant::now();
+            while shared_c.running.load(Ordering::Relaxed) {
+                next += interval;
+                let now = Instant::now();
+                if next > now {
+                    thread::sleep(next - now);
+                } else if now - next > interval {
+                    // Fell behind, probably suspended. Don't burst to catch up.
+                    next = now;
+                }
+                let activities = sampler.sample();
+                if activities.is_empty() {
+                    continue;
+                }
+                let sample = Sample {
+                    at: Instant::now(),
+                    interval,
+                    activities,
+                };
+                let mut subscribers = shared_c.subscribers.lock().unwrap();
+                if let Some(subscribers) = subscribers.as_mut() {
+                    subscribers.retain(|subscriber| {
+                        !matches!(
+                            subscriber.try_send(sample.clone()),
+                            Err(mpsc::TrySendError::Disconnected(_))
+                        )
+                    });
+                }
+            }
+        });
+        SamplerThread {
+            handle: Arc::new(ThreadHandle {
+                shared: shared.clone(),
+                handle: Some(handle),
+            }),
+            shared,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.shared.interval
+    }
+
+    /// Samples taken from now on. Disconnected right away if the thread
+    /// already stopped.
+    pub fn subscribe(&self) -> Subscription {
+        let (sender, samples) = mpsc::sync_channel(SUBSCRIPTION_BUFFER);
+        if let Some(subscribers) = self.shared.subscribers.lock().unwrap().as_mut() {
+            subscribers.push(sender);
+        }
+        Subscription {
+            samples,
+            interval: self.shared.interval,
+            _handle: self.handle.clone(),
+        }
+    }
+}
+
+impl Subscription {
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn try_iter(&self) -> mpsc::TryIter<'_, Sample> {
+        self.samples.try_iter()
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Sample, mpsc::RecvTimeoutError> {
+        self.samples.recv_timeout(timeout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input_source::{InputSnapshot, ScriptedInput};
+
+    #[test]
+    fn broadcasts_at_fixed_interval() {
+        let sampler_thread = SamplerThread::spawn_with(
+            || {
+                Ok(ScriptedInput::new([
+                    InputSnapshot::default(),
+                    InputSnapshot::default(),
+                    InputSnapshot {
+                        keys: vec![Keycode::A],
+                        ..Default::default()
+                    },
+                ]))
+            },
+            100,
+        );
+        let sampler = sampler_thread.subscribe();
+        let other = sampler_thread.subscribe();
+        drop(sampler_thread);
+        let sample = sampler.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(sample.interval, Duration::from_millis(10));
+        assert_eq!(
+            sample.activities,
+            vec![
+                (ActivityKind::KeyPress, 1),
+                (
+                    ActivityKind::KeyJustPress {
+                        class: KeyClass::Typing
+                    },
+                    1
+                ),
+            ]
+        );
+        // The key stays held.
+        let sample = sampler.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(sample.activities, vec![(ActivityKind::KeyPress, 1)]);
+        // Every subscriber sees the same samples.
+        other.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(other.recv_timeout(Duration::from_secs(5)).unwrap(), sample);
+    }
+
+    #[test]
+    fn failing_input_disconnects() {
+        let sampler_thread = SamplerThread::spawn_with(
+            || -> anyhow::Result<ScriptedInput> { anyhow::bail!("no input") },
+            100,
+        );
+        let early = sampler_thread.subscribe();
+        assert_eq!(
+            early.recv_timeout(Duration::from_secs(5)),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        );
+        let late = sampler_thread.subscribe();
+        assert_eq!(
+            late.recv_timeout(Duration::from_secs(5)),
+            Err(mpsc::RecvTimeoutError::Disconnected)
+        );
+    }
+}
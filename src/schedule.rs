@@ -1,8 +1,16 @@
+use anyhow::{bail, Context, Result};
 use bitflags::bitflags;
+use chrono::{
+    DateTime, Datelike, Duration as ChronoDuration, LocalResult, NaiveDate, NaiveDateTime,
+    NaiveTime, TimeZone, Utc, Weekday,
+};
+use chrono_tz::Tz;
+
+use crate::config::ScheduleConfig;
 
 bitflags! {
-    #[derive(Debug, Default)]
-    struct Day: u8 {
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub struct Day: u8 {
         const SUNDAY    = 0b0000_0001;
         const MONDAY    = 0b0000_0010;
         const TUESDAY   = 0b0000_0100;
@@ -10,17 +18,314 @@ bitflags! {
         const THURSDAY  = 0b0001_0000;
         const FRIDAY    = 0b0010_0000;
         const SATURDAY  = 0b0100_0000;
+
+        const WEEKDAYS  = 0b0011_1110;
+        const WEEKEND   = 0b0100_0001;
+    }
+}
+
+impl Day {
+    pub fn from_weekday(weekday: Weekday) -> Day {
+        match weekday {
+            Weekday::Sun => Day::SUNDAY,
+            Weekday::Mon => Day::MONDAY,
+            Weekday::Tue => Day::TUESDAY,
+            Weekday::Wed => Day::WEDNESDAY,
+            Weekday::Thu => Day::THURSDAY,
+            Weekday::Fri => Day::FRIDAY,
+            Weekday::Sat => Day::SATURDAY,
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Day> {
+        Ok(match name.to_ascii_lowercase().as_str() {
+            "sun" | "sunday" => Day::SUNDAY,
+            "mon" | "monday" => Day::MONDAY,
+            "tue" | "tuesday" => Day::TUESDAY,
+            "wed" | "wednesday" => Day::WEDNESDAY,
+            "thu" | "thursday" => Day::THURSDAY,
+            "fri" | "friday" => Day::FRIDAY,
+            "sat" | "saturday" => Day::SATURDAY,
+            "weekdays" => Day::WEEKDAYS,
+            "weekend" => Day::WEEKEND,
+            "all" | "everyday" => Day::all(),
+            _ => bail!("unknown day `{name}`"),
+        })
+    }
+}
+
+/// Time zone the schedule windows are written in.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ScheduleTimeZone {
+    /// Whatever the system considers local time, re-evaluated on every query
+    /// so a laptop that travels follows along.
+    #[default]
+    Local,
+    Iana(Tz),
+}
+
+impl ScheduleTimeZone {
+    pub fn parse(name: &str) -> Result<ScheduleTimeZone> {
+        if name.eq_ignore_ascii_case("local") {
+            return Ok(ScheduleTimeZone::Local);
+        }
+        let tz = name
+            .parse::<Tz>()
+            .map_err(|e| anyhow::anyhow!("{e}"))
+            .with_context(|| format!("invalid time zone `{name}`"))?;
+        Ok(ScheduleTimeZone::Iana(tz))
+    }
+
+    pub fn local_date(&self, at: DateTime<Utc>) -> NaiveDate {
+        match self {
+            ScheduleTimeZone::Local => at.with_timezone(&chrono::Local).date_naive(),
+            ScheduleTimeZone::Iana(tz) => at.with_timezone(tz).date_naive(),
+        }
+    }
+
+    pub fn format(&self, at: DateTime<Utc>, fmt: &str) -> String {
+        match self {
+            ScheduleTimeZone::Local => at.with_timezone(&chrono::Local).format(fmt).to_string(),
+            ScheduleTimeZone::Iana(tz) => at.with_timezone(tz).format(fmt).to_string(),
+        }
+    }
+
+    /// Maps a wall clock time to a single instant.
+    ///
+    /// Ambiguous times (fall-back) resolve to their first occurrence and times
+    /// inside a gap (spring-forward) resolve to the end of the gap, so every
+    /// wall clock time maps to exactly one instant.
+    pub fn resolve(&self, naive: NaiveDateTime) -> DateTime<Utc> {
+        match self {
+            ScheduleTimeZone::Local => resolve_in(&chrono::Local, naive),
+            ScheduleTimeZone::Iana(tz) => resolve_in(tz, naive),
+        }
+    }
+}
+
+fn resolve_in<T: TimeZone>(tz: &T, naive: NaiveDateTime) -> DateTime<Utc> {
+    let mut candidate = naive;
+    // Gaps are at most a couple of hours in practice. Walking forward a
+    // minute at a time lands on the first representable wall clock time.
+    for _ in 0..=(24 * 60) {
+        match tz.from_local_datetime(&candidate) {
+            LocalResult::Single(t) => return t.with_timezone(&Utc),
+            LocalResult::Ambiguous(earliest, _) => return earliest.with_timezone(&Utc),
+            LocalResult::None => candidate += ChronoDuration::minutes(1),
+        }
+    }
+    Utc.from_utc_datetime(&naive)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkWindow {
+    pub days: Day,
+    pub start: NaiveTime,
+    /// A window whose end is not after its start runs past midnight.
+    pub end: NaiveTime,
+}
+
+impl WorkWindow {
+    pub fn new(days: Day, start: NaiveTime, end: NaiveTime) -> WorkWindow {
+        WorkWindow { days, start, end }
+    }
+
+    fn occurrence_on(
+        &self,
+        date: NaiveDate,
+        time_zone: &ScheduleTimeZone,
+    ) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        if !self.days.contains(Day::from_weekday(date.weekday())) {
+            return None;
+        }
+        let end_date = if self.end > self.start {
+            date
+        } else {
+            date.succ_opt()?
+        };
+        let (naive_start, naive_end) = (date.and_time(self.start), end_date.and_time(self.end));
+        let start = time_zone.resolve(naive_start);
+        let mut end = time_zone.resolve(naive_end);
+        // A window entirely inside a spring-forward gap would resolve to
+        // nothing. It keeps its length from the end of the gap instead.
+        if end <= start {
+            end = start + (naive_end - naive_start);
+        }
+        Some((start, end))
     }
 }
 
 #[derive(Debug, Default)]
-struct Schedule {
-    auto_start_at_time: Option<()>,
-    auto_start_at_day: Option<Day>,
+pub struct Schedule {
+    time_zone: ScheduleTimeZone,
+    windows: Vec<WorkWindow>,
 }
 
 impl Schedule {
     pub fn new() -> Schedule {
         Schedule::default()
     }
+
+    pub fn from_config(config: &ScheduleConfig) -> Result<Schedule> {
+        let time_zone = match &config.time_zone {
+            Some(name) => ScheduleTimeZone::parse(name)?,
+            None => ScheduleTimeZone::Local,
+        };
+        let mut schedule = Schedule::new().with_time_zone(time_zone);
+        for window in &config.windows {
+            let mut days = Day::empty();
+            for day in &window.days {
+                days |= Day::parse(day)?;
+            }
+            let start = parse_time(&window.start)?;
+            let end = parse_time(&window.end)?;
+            schedule.add_window(WorkWindow::new(days, start, end));
+        }
+        Ok(schedule)
+    }
+
+    pub fn with_time_zone(mut self, time_zone: ScheduleTimeZone) -> Schedule {
+        self.time_zone = time_zone;
+        self
+    }
+
+    pub fn time_zone(&self) -> ScheduleTimeZone {
+        self.time_zone
+    }
+
+    pub fn add_window(&mut self, window: WorkWindow) {
+        self.windows.push(window);
+    }
+
+    pub fn windows(&self) -> &[WorkWindow] {
+        &self.windows
+    }
+
+    /// A schedule without windows doesn't restrict anything.
+    pub fn is_unrestricted(&self) -> bool {
+        self.windows.is_empty()
+    }
+
+    pub fn is_active_at(&self, at: DateTime<Utc>) -> bool {
+        self.is_unrestricted() || self.window_at(at).is_some()
+    }
+
+    /// The window occurrence containing `at`, as `(start, end)`.
+    pub fn window_at(&self, at: DateTime<Utc>) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let date = self.time_zone.local_date(at);
+        // The previous day is checked for windows running past midnight.
+        self.occurrences_between(date.pred_opt()?, date)
+            .into_iter()
+            .find(|(start, end)| *start <= at && at < *end)
+    }
+
+    /// The first window start strictly after `after`.
+    pub fn next_window_start(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let date = self.time_zone.local_date(after);
+        self.occurrences_between(date.pred_opt()?, date + ChronoDuration::days(8))
+            .into_iter()
+            .map(|(start, _)| start)
+            .find(|start| *start > after)
+    }
+
+    /// Every window occurrence starting on a local date in `from..=to`, sorted
+    /// by start.
+    pub fn occurrences_between(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+        let mut occurrences = Vec::new();
+        let mut date = from;
+        while date <= to {
+            occurrences.extend(
+                self.windows
+                    .iter()
+                    .filter_map(|window| window.occurrence_on(date, &self.time_zone)),
+            );
+            let Some(next) = date.succ_opt() else {
+                break;
+            };
+            date = next;
+        }
+        occurrences.sort();
+        occurrences
+    }
+}
+
+fn parse_time(s: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M:%S"))
+        .with_context(|| format!("invalid time `{s}`, expected HH:MM"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn new_york() -> ScheduleTimeZone {
+        ScheduleTimeZone::Iana(chrono_tz::America::New_York)
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn time(s: &str) -> NaiveTime {
+        parse_time(s).unwrap()
+    }
+
+    #[test]
+    fn spring_forward_gap_is_not_skipped() {
+        // 2023-03-12 02:00 EST jumps to 03:00 EDT, so 02:30 never exists.
+        let mut schedule = Schedule::new().with_time_zone(new_york());
+        schedule.add_window(WorkWindow::new(Day::all(), time("02:30"), time("04:00")));
+
+        let start = schedule
+            .next_window_start(utc("2023-03-12T05:00:00Z"))
+            .unwrap();
+        assert_eq!(start, utc("2023-03-12T07:00:00Z"));
+        assert!(schedule.is_active_at(utc("2023-03-12T07:30:00Z")));
+        assert!(!schedule.is_active_at(utc("2023-03-12T06:59:00Z")));
+        assert_eq!(
+            schedule.next_window_start(start),
+            Some(utc("2023-03-13T06:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn window_inside_spring_forward_gap_keeps_its_length() {
+        let mut schedule = Schedule::new().with_time_zone(new_york());
+        schedule.add_window(WorkWindow::new(Day::all(), time("02:30"), time("02:45")));
+
+        let (start, end) = schedule.window_at(utc("2023-03-12T07:05:00Z")).unwrap();
+        assert_eq!(start, utc("2023-03-12T07:00:00Z"));
+        assert_eq!(end, utc("2023-03-12T07:15:00Z"));
+        assert!(!schedule.is_active_at(utc("2023-03-12T07:15:00Z")));
+    }
+
+    #[test]
+    fn fall_back_overlap_fires_once() {
+        // 2023-11-05 02:00 EDT falls back to 01:00 EST, so 01:30 happens twice.
+        let mut schedule = Schedule::new().with_time_zone(new_york());
+        schedule.add_window(WorkWindow::new(Day::all(), time("01:30"), time("01:45")));
+
+        let first = schedule
+            .next_window_start(utc("2023-11-05T04:00:00Z"))
+            .unwrap();
+        assert_eq!(first, utc("2023-11-05T05:30:00Z"));
+        let second = schedule.next_window_start(first).unwrap();
+        assert_eq!(second, utc("2023-11-06T06:30:00Z"));
+        assert!(!schedule.is_active_at(utc("2023-11-05T06:35:00Z")));
+    }
+
+    #[test]
+    fn window_past_midnight() {
+        let mut schedule = Schedule::new().with_time_zone(new_york());
+        schedule.add_window(WorkWindow::new(Day::FRIDAY, time("22:00"), time("02:00")));
+
+        // Saturday 01:00 in New York still belongs to Friday's window.
+        assert!(schedule.is_active_at(utc("2023-06-03T05:00:00Z")));
+        assert!(!schedule.is_active_at(utc("2023-06-03T07:00:00Z")));
+    }
 }
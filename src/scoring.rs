@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::activity_monitor::{ActivityKind, ActivityMonitor, KeyClass};
+
+/// How much one kind of activity is worth.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct KindScore {
+    /// Per unit of activity per second. For mouse moves the unit is a pixel.
+    pub weight: f64,
+    /// The most a single sample can score, before the frame's delta.
+    pub cap: Option<f64>,
+}
+
+impl KindScore {
+    pub const fn new(weight: f64) -> KindScore {
+        KindScore { weight, cap: None }
+    }
+
+    fn score(&self, units: f64) -> f64 {
+        let score = self.weight * units;
+        match self.cap {
+            Some(cap) => score.min(cap),
+            None => score,
+        }
+    }
+}
+
+/// Weights for each [`ActivityKind`]. Anything a profile in the config file
+/// leaves out keeps the `default` profile's value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScoringProfile {
+    pub key_press: KindScore,
+    /// Typing keys, see [`KeyClass`].
+    pub key_just_press: KindScore,
+    pub shortcut_just_press: KindScore,
+    pub navigation_just_press: KindScore,
+    pub mouse_pressed: KindScore,
+    pub mouse_just_pressed: KindScore,
+    pub mouse_move: KindScore,
+    pub mouse_drag: KindScore,
+    pub scroll: KindScore,
+    /// Don't score shift, control, alt and meta on their own. They still
+    /// count as part of a shortcut through the other key.
+    pub ignore_modifier_only: bool,
+}
+
+impl Default for ScoringProfile {
+    fn default() -> Self {
+        ScoringProfile {
+            key_press: KindScore::new(1.0),
+            key_just_press: KindScore::new(75.0),
+            shortcut_just_press: KindScore::new(75.0),
+            navigation_just_press: KindScore::new(25.0),
+            mouse_pressed: KindScore::new(1.0),
+            mouse_just_pressed: KindScore::new(75.0),
+            mouse_move: KindScore::new(0.1),
+            mouse_drag: KindScore::new(0.2),
+            scroll: KindScore::new(10.0),
+            ignore_modifier_only: false,
+        }
+    }
+}
+
+impl ScoringProfile {
+    /// Typing counts for more than pointing around.
+    pub fn typing() -> ScoringProfile {
+        ScoringProfile {
+            key_just_press: KindScore::new(100.0),
+            shortcut_just_press: KindScore::new(100.0),
+            navigation_just_press: KindScore::new(15.0),
+            mouse_move: KindScore::new(0.05),
+            scroll: KindScore::new(5.0),
+            ignore_modifier_only: true,
+            ..Default::default()
+        }
+    }
+
+    /// Drawing, CAD and the like, where the mouse is the main input.
+    pub fn mouse() -> ScoringProfile {
+        ScoringProfile {
+            mouse_pressed: KindScore::new(5.0),
+            mouse_just_pressed: KindScore::new(100.0),
+            mouse_move: KindScore::new(0.2),
+            mouse_drag: KindScore::new(0.4),
+            ..Default::default()
+        }
+    }
+
+    /// Keys are held for long stretches and the mouse flies around, so both
+    /// are capped to keep a match from scoring like hours of work.
+    pub fn gaming() -> ScoringProfile {
+        ScoringProfile {
+            key_press: KindScore {
+                weight: 0.5,
+                cap: Some(2.0),
+            },
+            key_just_press: KindScore {
+                weight: 50.0,
+                cap: Some(150.0),
+            },
+            // Arrow keys steer.
+            navigation_just_press: KindScore {
+                weight: 50.0,
+                cap: Some(150.0),
+            },
+            mouse_move: KindScore {
+                weight: 0.05,
+                cap: Some(50.0),
+            },
+            mouse_drag: KindScore {
+                weight: 0.05,
+                cap: Some(50.0),
+            },
+            ignore_modifier_only: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn builtin(name: &str) -> Option<ScoringProfile> {
+        match name {
+            "default" => Some(ScoringProfile::default()),
+            "typing" => Some(ScoringProfile::typing()),
+            "mouse" => Some(ScoringProfile::mouse()),
+            "gaming" => Some(ScoringProfile::gaming()),
+            _ => None,
+        }
+    }
+
+    /// Looks `name` up in the configured profiles first, then the built-in
+    /// ones.
+    pub fn find(name: &str, configured: &BTreeMap<String, ScoringProfile>) -> Result<Self> {
+        if let Some(profile) = configured.get(name) {
+            return Ok(profile.clone());
+        }
+        match ScoringProfile::builtin(name) {
+            Some(profile) => Ok(profile),
+            None => bail!("unknown activity profile `{name}`"),
+        }
+    }
+
+    pub fn score(&self, activity_kind: ActivityKind, amount: usize) -> f64 {
+        let amount = amount as f64;
+        match activity_kind {
+            ActivityKind::KeyPress => self.key_press.score(amount),
+            ActivityKind::KeyJustPress { class } => match class {
+                KeyClass::Typing => self.key_just_press.score(amount),
+                KeyClass::Shortcut => self.shortcut_just_press.score(amount),
+                KeyClass::Navigation => self.navigation_just_press.score(amount),
+            },
+            ActivityKind::ModifierPress if self.ignore_modifier_only => 0.0,
+            ActivityKind::ModifierPress => self.key_press.score(amount),
+            ActivityKind::ModifierJustPress if self.ignore_modifier_only => 0.0,
+            ActivityKind::ModifierJustPress => self.key_just_press.score(amount),
+            ActivityKind::MousePressed => self.mouse_pressed.score(amount),
+            ActivityKind::MouseJustPressed => self.mouse_just_pressed.score(amount),
+            ActivityKind::MouseMove { distance } => self.mouse_move.score(distance * amount),
+            ActivityKind::MouseDrag { distance } => self.mouse_drag.score(distance * amount),
+            ActivityKind::Scroll => self.scroll.score(amount),
+        }
+    }
+
+    pub fn into_activity_fn(self) -> impl Fn(&ActivityMonitor, ActivityKind, usize) -> f64 {
+        move |_, activity_kind, amount| self.score(activity_kind, amount)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn partial_profiles_keep_defaults() {
+        let profiles: BTreeMap<String, ScoringProfile> = toml::from_str(
+            r#"
+            [writing]
+            ignore_modifier_only = true
+            key_just_press = { weight = 120.0, cap = 200.0 }
+            "#,
+        )
+        .unwrap();
+        let profile = ScoringProfile::find("writing", &profiles).unwrap();
+        let typing = ActivityKind::KeyJustPress {
+            class: KeyClass::Typing,
+        };
+        assert_eq!(profile.score(typing, 3), 200.0);
+        assert_eq!(profile.score(ActivityKind::ModifierJustPress, 1), 0.0);
+        assert_eq!(profile.score(ActivityKind::Scroll, 2), 20.0);
+        assert_eq!(
+            ScoringProfile::find("gaming", &profiles).unwrap(),
+            ScoringProfile::gaming()
+        );
+        assert!(ScoringProfile::find("typo", &profiles).is_err());
+    }
+}
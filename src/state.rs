@@ -0,0 +1,128 @@
+//! Things remembered between runs that aren't configuration, kept in
+//! `state.toml` next to where the app keeps its data.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::enforcement::BreakPreference;
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    /// Remembered answers to how breaks are enforced, by `breaks.profile`.
+    pub break_preferences: BTreeMap<String, BreakPreference>,
+    /// The break that was running when the app last stopped, so a restart
+    /// doesn't end it.
+    pub current_break: Option<SavedBreak>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SavedBreak {
+    pub started_at: DateTime<Utc>,
+    pub long: bool,
+    pub duration_secs: u64,
+}
+
+impl SavedBreak {
+    /// What's left of the break at `now`, `None` once it's over.
+    pub fn remaining_at(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let end = self.started_at + chrono::Duration::seconds(self.duration_secs as i64);
+        (end - now)
+            .to_std()
+            .ok()
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    pub fn elapsed_at(&self, now: DateTime<Utc>) -> Duration {
+        (now - self.started_at).to_std().unwrap_or_default()
+    }
+}
+
+impl State {
+    pub fn dir() -> Option<PathBuf> {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .map(|dir| dir.join("pomodoro-ss"))
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        State::dir().map(|dir| dir.join("state.toml"))
+    }
+
+    /// Loads the state file, or nothing remembered if there isn't one.
+    pub fn load() -> Result<State> {
+        match State::path() {
+            Some(path) => State::load_from(&path),
+            None => Ok(State::default()),
+        }
+    }
+
+    pub fn load_from(path: &Path) -> Result<State> {
+        if !path.exists() {
+            return Ok(State::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    pub fn save(&self) -> Result<PathBuf> {
+        let path = State::path().context("no data directory on this system")?;
+        self.save_to(&path)?;
+        Ok(path)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, toml::to_string(self)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("pomodoro-ss-state-{}.toml", std::process::id()));
+        assert_eq!(State::load_from(&path).unwrap(), State::default());
+
+        let mut state = State::default();
+        state.break_preferences.insert(
+            "typing".to_string(),
+            BreakPreference::Hibernate {
+                let_user_prepare: false,
+            },
+        );
+        state.current_break = Some(SavedBreak {
+            started_at: "2023-03-20T09:30:00Z".parse().unwrap(),
+            long: true,
+            duration_secs: 900,
+        });
+        state.save_to(&path).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(content.contains("action = \"hibernate\""));
+        assert_eq!(State::load_from(&path).unwrap(), state);
+        fs::remove_file(&path).unwrap();
+
+        let saved = state.current_break.unwrap();
+        let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(
+            saved.remaining_at(at("2023-03-20T09:40:00Z")),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(saved.remaining_at(at("2023-03-20T09:45:00Z")), None);
+    }
+}
@@ -1,6 +1,11 @@
-use std::time::Duration;
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
 
-use crate::World;
+use chrono::{DateTime, Utc};
+
+use crate::{World, SINCE_START};
 
 #[derive(Debug, Clone)]
 pub struct Stopwatch {
@@ -74,8 +79,52 @@ impl Timer {
     }
 }
 
+/// Hands out worlds with a fixed delta so anything driven by [`World`] can be
+/// simulated exactly and deterministically.
+#[derive(Debug, Clone)]
+pub struct SimulatedClock {
+    now: Instant,
+    wall_clock: DateTime<Utc>,
+    step: Duration,
+}
+
+impl SimulatedClock {
+    pub fn new(start: DateTime<Utc>, step: Duration) -> SimulatedClock {
+        SimulatedClock {
+            now: Instant::now(),
+            wall_clock: start,
+            step,
+        }
+    }
+
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+
+    pub fn wall_clock(&self) -> DateTime<Utc> {
+        self.wall_clock
+    }
+
+    /// Advances the clock by one step.
+    pub fn tick(&mut self) -> World {
+        self.advance(self.step)
+    }
+
+    /// Advances the clock by `delta` instead of the usual step.
+    pub fn advance(&mut self, delta: Duration) -> World {
+        self.wall_clock += chrono::Duration::from_std(delta).unwrap_or(chrono::Duration::zero());
+        self.now += delta;
+        World {
+            delta,
+            system_since_start: &SINCE_START,
+            now: self.now,
+            wall_clock: self.wall_clock,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
-struct FormattedDuration {
+pub struct FormattedDuration {
     hours: u32,
     minutes: u32,
     seconds: u32,
@@ -96,6 +145,17 @@ impl FormattedDuration {
     }
 }
 
+impl fmt::Display for FormattedDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.hours, self.minutes, self.seconds) {
+            (0, 0, s) => write!(f, "{s}s"),
+            (0, m, 0) => write!(f, "{m}m"),
+            (0, m, s) => write!(f, "{m}m {s:02}s"),
+            (h, m, _) => write!(f, "{h}h {m:02}m"),
+        }
+    }
+}
+
 impl From<FormattedDuration> for Duration {
     fn from(value: FormattedDuration) -> Self {
         let secs =
@@ -127,4 +187,24 @@ mod test {
             .into()
         )
     }
+
+    #[test]
+    fn formatted_duration_display() {
+        assert_eq!(
+            FormattedDuration::new(Duration::from_secs(45)).to_string(),
+            "45s"
+        );
+        assert_eq!(
+            FormattedDuration::new(Duration::from_secs(300)).to_string(),
+            "5m"
+        );
+        assert_eq!(
+            FormattedDuration::new(Duration::from_secs(330)).to_string(),
+            "5m 30s"
+        );
+        assert_eq!(
+            FormattedDuration::new(Duration::from_secs(4500)).to_string(),
+            "1h 15m"
+        );
+    }
 }
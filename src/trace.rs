@@ -0,0 +1,562 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+
+use crate::{
+    activity_monitor::{ActivityKind, ActivityMonitor, KeyClass},
+    break_notifier::{ActivityBreak, BreakState},
+    config::ActivityConfig,
+    input_source::NoInput,
+    safety,
+    scoring::ScoringProfile,
+    time::{FormattedDuration, SimulatedClock},
+};
+
+const HEADER: &str = "# pomodoro-ss activity trace v2";
+
+/// The longest the simulated clock moves in one go through gaps in a trace.
+const REPLAY_STEP: Duration = Duration::from_secs(1);
+
+/// How much of a recording goes into one frame of the trace.
+const BUCKET: Duration = Duration::from_secs(1);
+
+/// The samples of one [`BUCKET`]. Only frames with activity are written, one
+/// line each: `<offset µs> <delta µs> <interval µs> <kind>=<amount>x<samples> ...`,
+/// where the offset is the time since recording started at the last sample,
+/// the delta how much of the bucket that covers, and the interval the mean
+/// time between samples. Each activity is an amount seen in that many
+/// samples, `x1` being left out. Mouse moves and drags carry their mean
+/// distance instead of an amount. Lines of v1 traces have no interval and
+/// are single samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub offset: Duration,
+    pub delta: Duration,
+    pub interval: Duration,
+    pub activities: Vec<(ActivityKind, usize, u32)>,
+}
+
+fn kind_name(activity_kind: ActivityKind) -> &'static str {
+    match activity_kind {
+        ActivityKind::KeyPress => "key_press",
+        ActivityKind::KeyJustPress {
+            class: KeyClass::Typing,
+        } => "key_just_press.typing",
+        ActivityKind::KeyJustPress {
+            class: KeyClass::Shortcut,
+        } => "key_just_press.shortcut",
+        ActivityKind::KeyJustPress {
+            class: KeyClass::Navigation,
+        } => "key_just_press.navigation",
+        ActivityKind::ModifierPress => "modifier_press",
+        ActivityKind::ModifierJustPress => "modifier_just_press",
+        ActivityKind::MousePressed => "mouse_pressed",
+        ActivityKind::MouseJustPressed => "mouse_just_pressed",
+        ActivityKind::MouseMove { .. } => "mouse_move",
+        ActivityKind::MouseDrag { .. } => "mouse_drag",
+        ActivityKind::Scroll => "scroll",
+    }
+}
+
+fn parse_activity(token: &str) -> Result<(ActivityKind, usize, u32)> {
+    let Some((name, value)) = token.split_once('=') else {
+        bail!("expected `kind=amount`, got `{token}`");
+    };
+    let (value, samples) = match value.split_once('x') {
+        Some((value, samples)) => (
+            value,
+            samples
+                .parse()
+                .with_context(|| format!("invalid sample count `{samples}`"))?,
+        ),
+        None => (value, 1),
+    };
+    let distance = || -> Result<f64> {
+        value
+            .parse()
+            .with_context(|| format!("invalid distance `{value}`"))
+    };
+    let activity_kind = match name {
+        "mouse_move" => {
+            return Ok((
+                ActivityKind::MouseMove {
+                    distance: distance()?,
+                },
+                1,
+                samples,
+            ))
+        }
+        "mouse_drag" => {
+            return Ok((
+                ActivityKind::MouseDrag {
+                    distance: distance()?,
+                },
+                1,
+                samples,
+            ))
+        }
+        "key_press" => ActivityKind::KeyPress,
+        "key_just_press.typing" => ActivityKind::KeyJustPress {
+            class: KeyClass::Typing,
+        },
+        "key_just_press.shortcut" => ActivityKind::KeyJustPress {
+            class: KeyClass::Shortcut,
+        },
+        "key_just_press.navigation" => ActivityKind::KeyJustPress {
+            class: KeyClass::Navigation,
+        },
+        "modifier_press" => ActivityKind::ModifierPress,
+        "modifier_just_press" => ActivityKind::ModifierJustPress,
+        "mouse_pressed" => ActivityKind::MousePressed,
+        "mouse_just_pressed" => ActivityKind::MouseJustPressed,
+        "scroll" => ActivityKind::Scroll,
+        _ => bail!("unknown activity `{name}`"),
+    };
+    let amount = value
+        .parse()
+        .with_context(|| format!("invalid amount `{value}`"))?;
+    Ok((activity_kind, amount, samples))
+}
+
+impl TraceFrame {
+    pub fn to_line(&self) -> String {
+        let mut line = format!(
+            "{} {} {}",
+            self.offset.as_micros(),
+            self.delta.as_micros(),
+            self.interval.as_micros()
+        );
+        for (activity_kind, amount, samples) in &self.activities {
+            let value = match activity_kind {
+                ActivityKind::MouseMove { distance } | ActivityKind::MouseDrag { distance } => {
+                    format!("{distance:.1}")
+                }
+                _ => amount.to_string(),
+            };
+            line.push_str(&format!(" {}={value}", kind_name(*activity_kind)));
+            if *samples != 1 {
+                line.push_str(&format!("x{samples}"));
+            }
+        }
+        line
+    }
+
+    pub fn parse(line: &str) -> Result<TraceFrame> {
+        let mut tokens = line.split_whitespace().peekable();
+        let micros = |token: Option<&str>, what: &str| -> Result<Duration> {
+            let token = token.with_context(|| format!("missing {what}"))?;
+            let micros = token
+                .parse()
+                .with_context(|| format!("invalid {what} `{token}`"))?;
+            Ok(Duration::from_micros(micros))
+        };
+        let offset = micros(tokens.next(), "offset")?;
+        let delta = micros(tokens.next(), "delta")?;
+        let interval = match tokens.next_if(|token| !token.contains('=')) {
+            Some(token) => micros(Some(token), "interval")?,
+            None => delta,
+        };
+        let activities = tokens.map(parse_activity).collect::<Result<_>>()?;
+        Ok(TraceFrame {
+            offset,
+            delta,
+            interval,
+            activities,
+        })
+    }
+
+    /// The score of every sample in the frame under `profile`, as
+    /// `ActivityMonitor` would have added them up.
+    pub fn score(&self, profile: &ScoringProfile) -> f64 {
+        self.activities
+            .iter()
+            .map(|(activity_kind, amount, samples)| {
+                profile.score(*activity_kind, *amount) * (self.interval * *samples).as_secs_f64()
+            })
+            .sum()
+    }
+}
+
+/// Collects samples until a [`BUCKET`] is full.
+#[derive(Default)]
+struct Bucket {
+    index: u128,
+    offset: Duration,
+    intervals: Duration,
+    samples: u32,
+    activities: Vec<(ActivityKind, usize, u32)>,
+}
+
+impl Bucket {
+    /// Adds a sample taken `offset` into the recording, handing back the
+    /// bucket before if the sample is the first of a new one.
+    fn add(
+        &mut self,
+        offset: Duration,
+        interval: Duration,
+        activities: Vec<(ActivityKind, usize)>,
+    ) -> Option<TraceFrame> {
+        let index = offset.as_micros() / BUCKET.as_micros();
+        let finished = if index == self.index {
+            None
+        } else {
+            let finished = self.take();
+            self.index = index;
+            finished
+        };
+        self.offset = offset;
+        self.intervals += interval;
+        self.samples += 1;
+        for (activity_kind, amount) in activities {
+            let seen = self.activities.iter_mut().find(|(seen, seen_amount, _)| {
+                match (*seen, activity_kind) {
+                    (ActivityKind::MouseMove { .. }, ActivityKind::MouseMove { .. })
+                    | (ActivityKind::MouseDrag { .. }, ActivityKind::MouseDrag { .. }) => true,
+                    _ => *seen == activity_kind && *seen_amount == amount,
+                }
+            });
+            match (seen, activity_kind) {
+                (
+                    Some((
+                        ActivityKind::MouseMove { distance: total }
+                        | ActivityKind::MouseDrag { distance: total },
+                        _,
+                        samples,
+                    )),
+                    ActivityKind::MouseMove { distance } | ActivityKind::MouseDrag { distance },
+                ) => {
+                    *total += distance * amount as f64;
+                    *samples += 1;
+                }
+                (Some((_, _, samples)), _) => *samples += 1,
+                (None, ActivityKind::MouseMove { distance }) => self.activities.push((
+                    ActivityKind::MouseMove {
+                        distance: distance * amount as f64,
+                    },
+                    1,
+                    1,
+                )),
+                (None, ActivityKind::MouseDrag { distance }) => self.activities.push((
+                    ActivityKind::MouseDrag {
+                        distance: distance * amount as f64,
+                    },
+                    1,
+                    1,
+                )),
+                (None, _) => self.activities.push((activity_kind, amount, 1)),
+            }
+        }
+        finished
+    }
+
+    /// The frame for the samples so far, if there were any.
+    fn take(&mut self) -> Option<TraceFrame> {
+        if self.samples == 0 {
+            return None;
+        }
+        let mut activities = std::mem::take(&mut self.activities);
+        for (activity_kind, _, samples) in &mut activities {
+            if let ActivityKind::MouseMove { distance } | ActivityKind::MouseDrag { distance } =
+                activity_kind
+            {
+                *distance /= *samples as f64;
+            }
+        }
+        let start = Duration::from_micros((self.index * BUCKET.as_micros()) as u64);
+        let frame = TraceFrame {
+            offset: self.offset,
+            delta: self.offset.saturating_sub(start),
+            interval: self.intervals / self.samples,
+            activities,
+        };
+        self.intervals = Duration::ZERO;
+        self.samples = 0;
+        Some(frame)
+    }
+}
+
+pub fn read_trace(path: &Path) -> Result<Vec<TraceFrame>> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+    let mut frames = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let frame =
+            TraceFrame::parse(&line).with_context(|| format!("{}:{}", path.display(), i + 1))?;
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+/// Samples input until `duration` is up, a signal asks to stop, or forever,
+/// handing a frame for every [`BUCKET`] with activity to `on_frame`.
+pub fn sample<F>(config: &ActivityConfig, duration: Option<Duration>, mut on_frame: F) -> Result<()>
+where
+    F: FnMut(TraceFrame) -> Result<()>,
+{
+    let samples = ActivityMonitor::spawn_sampler(config).subscribe();
+    let start = Instant::now();
+    let mut bucket = Bucket::default();
+    while duration.is_none_or(|duration| start.elapsed() < duration) && !safety::exit_requested() {
+        match samples.recv_timeout(Duration::from_millis(100)) {
+            Ok(sample) => {
+                let offset = sample.at.saturating_duration_since(start);
+                if let Some(frame) = bucket.add(offset, sample.interval, sample.activities) {
+                    on_frame(frame)?;
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => bail!("input sampling stopped"),
+        }
+    }
+    match bucket.take() {
+        Some(frame) => on_frame(frame),
+        None => Ok(()),
+    }
+}
+
+/// Records to `path`. Ctrl-C stops the recording and everything recorded
+/// until then is written. The file is also flushed every second, so being
+/// killed outright loses at most that much.
+pub fn record(path: &Path, config: &ActivityConfig, duration: Option<Duration>) -> Result<()> {
+    safety::install(None)?;
+    let file =
+        File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    writeln!(writer, "{HEADER}")?;
+    let mut last_flush = Instant::now();
+
+    sample(config, duration, |frame| {
+        writeln!(writer, "{}", frame.to_line())?;
+        if last_flush.elapsed() >= Duration::from_secs(1) {
+            last_flush = Instant::now();
+            writer.flush()?;
+        }
+        Ok(())
+    })?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayEvent {
+    BreakStarts { active_for: Duration },
+    BreakEnds,
+}
+
+/// Feeds a trace through an [`ActivityBreak`] set up from `config`, with
+/// the gaps between frames filled in by a simulated clock.
+pub fn replay(
+    frames: &[TraceFrame],
+    config: &ActivityConfig,
+) -> Result<Vec<(Duration, ReplayEvent)>> {
+    let activity_monitor = ActivityMonitor::with_input_source(
+        NoInput,
+        config.scoring_profile()?.into_activity_fn(),
+        1,
+    );
+    let mut activity_break = ActivityBreak::from_config(config, activity_monitor);
+    let mut clock = SimulatedClock::new(Utc::now(), REPLAY_STEP);
+    let mut elapsed = Duration::ZERO;
+    let mut events = Vec::new();
+
+    for frame in frames {
+        let frame_start = frame.offset.saturating_sub(frame.delta);
+        while elapsed < frame_start {
+            let step = (frame_start - elapsed).min(REPLAY_STEP);
+            let world = clock.advance(step);
+            elapsed += step;
+            let before = activity_break.break_state();
+            activity_break.update(&world);
+            push_transition(&mut events, elapsed, before, &activity_break);
+        }
+        let world = clock.advance(frame.delta);
+        elapsed += frame.delta;
+        for (activity_kind, amount, samples) in &frame.activities {
+            activity_break.activity_monitor_mut().add_activity(
+                *activity_kind,
+                *amount,
+                world.now(),
+                frame.interval * *samples,
+            );
+        }
+        let before = activity_break.break_state();
+        activity_break.update(&world);
+        push_transition(&mut events, elapsed, before, &activity_break);
+    }
+    Ok(events)
+}
+
+fn push_transition(
+    events: &mut Vec<(Duration, ReplayEvent)>,
+    elapsed: Duration,
+    before: BreakState,
+    activity_break: &ActivityBreak,
+) {
+    match (before, activity_break.break_state()) {
+        (BreakState::NotBreak, BreakState::Break) => events.push((
+            elapsed,
+            ReplayEvent::BreakStarts {
+                active_for: activity_break.time_active(),
+            },
+        )),
+        (BreakState::Break, BreakState::NotBreak) => events.push((elapsed, ReplayEvent::BreakEnds)),
+        _ => {}
+    }
+}
+
+pub fn print_replay(path: &Path, config: &ActivityConfig) -> Result<()> {
+    let frames = read_trace(path)?;
+    let Some(last) = frames.last() else {
+        println!("The trace has no activity in it.");
+        return Ok(());
+    };
+    let events = replay(&frames, config)?;
+    println!(
+        "Replayed {} of activity with the `{}` profile.",
+        FormattedDuration::new(last.offset),
+        config.profile
+    );
+    if events.is_empty() {
+        println!("No break would have fired.");
+    }
+    for (at, event) in events {
+        let at = FormattedDuration::new(at).to_string();
+        match event {
+            ReplayEvent::BreakStarts { active_for } => println!(
+                "{at:>8}  break starts after {} of high activity",
+                FormattedDuration::new(active_for)
+            ),
+            ReplayEvent::BreakEnds => println!("{at:>8}  break ends"),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn frame_round_trip() {
+        let frame = TraceFrame {
+            offset: Duration::from_micros(1_520_000),
+            delta: Duration::from_micros(520_000),
+            interval: Duration::from_micros(10_250),
+            activities: vec![
+                (ActivityKind::KeyPress, 2, 30),
+                (
+                    ActivityKind::KeyJustPress {
+                        class: KeyClass::Shortcut,
+                    },
+                    1,
+                    1,
+                ),
+                (ActivityKind::MouseDrag { distance: 12.5 }, 1, 4),
+            ],
+        };
+        let line = frame.to_line();
+        assert_eq!(
+            line,
+            "1520000 520000 10250 key_press=2x30 key_just_press.shortcut=1 mouse_drag=12.5x4"
+        );
+        assert_eq!(TraceFrame::parse(&line).unwrap(), frame);
+        assert!(TraceFrame::parse("10 10 10 key_press=two").is_err());
+
+        // v1 lines are single samples.
+        let frame = TraceFrame::parse("20000 10000 key_press=1").unwrap();
+        assert_eq!(frame.interval, frame.delta);
+        assert_eq!(frame.activities, [(ActivityKind::KeyPress, 1, 1)]);
+    }
+
+    #[test]
+    fn samples_collect_into_buckets() {
+        let interval = Duration::from_millis(20);
+        let mut bucket = Bucket::default();
+        for i in 1..=60u64 {
+            let offset = interval * i as u32;
+            let mut activities = vec![(ActivityKind::KeyPress, 1 + (i % 2) as usize)];
+            if i % 10 == 0 {
+                activities.push((ActivityKind::MouseMove { distance: i as f64 }, 1));
+            }
+            let finished = bucket.add(offset, interval, activities);
+            assert_eq!(finished.is_some(), i == 50, "sample {i}");
+            let Some(frame) = finished else {
+                continue;
+            };
+            assert_eq!(frame.offset, Duration::from_millis(980));
+            assert_eq!(frame.delta, Duration::from_millis(980));
+            assert_eq!(frame.interval, interval);
+            assert_eq!(
+                frame.activities,
+                [
+                    (ActivityKind::KeyPress, 2, 25),
+                    (ActivityKind::KeyPress, 1, 24),
+                    (ActivityKind::MouseMove { distance: 25.0 }, 1, 4),
+                ]
+            );
+            // Scores as much as the samples one by one.
+            let profile = ScoringProfile::default();
+            let one_by_one = 25.0 * profile.score(ActivityKind::KeyPress, 2)
+                + 24.0 * profile.score(ActivityKind::KeyPress, 1)
+                + [10.0, 20.0, 30.0, 40.0]
+                    .map(|distance| profile.score(ActivityKind::MouseMove { distance }, 1))
+                    .iter()
+                    .sum::<f64>();
+            assert!((frame.score(&profile) - one_by_one * 0.02).abs() < 1e-9);
+        }
+        let frame = bucket.take().unwrap();
+        assert_eq!(frame.offset, Duration::from_millis(1200));
+        assert_eq!(frame.delta, Duration::from_millis(200));
+        assert_eq!(frame.activities.len(), 3);
+        assert!(bucket.take().is_none());
+    }
+
+    #[test]
+    fn replay_fires_after_sustained_activity() {
+        // A key held down for ten minutes, then nothing for five.
+        let frames: Vec<_> = (1..=600)
+            .map(|s| TraceFrame {
+                offset: Duration::from_secs(s),
+                delta: Duration::from_secs(1),
+                interval: Duration::from_millis(20),
+                activities: vec![(ActivityKind::KeyPress, 5, 50)],
+            })
+            .chain([TraceFrame {
+                offset: Duration::from_secs(900),
+                delta: Duration::from_secs(1),
+                interval: Duration::from_millis(20),
+                activities: vec![],
+            }])
+            .collect();
+        let config = ActivityConfig {
+            active_secs: 5 * 60,
+            idle_secs: 60,
+            break_secs: 60,
+            ..Default::default()
+        };
+
+        let events = replay(&frames, &config).unwrap();
+        let starts: Vec<_> = events
+            .iter()
+            .filter(|(_, event)| matches!(event, ReplayEvent::BreakStarts { .. }))
+            .collect();
+        assert_eq!(starts.len(), 1);
+        let (at, ReplayEvent::BreakStarts { active_for }) = starts[0] else {
+            unreachable!()
+        };
+        assert!(*active_for >= config.active_duration());
+        // The level needs a few seconds of the window to reach the threshold.
+        assert!(*at < Duration::from_secs(330));
+        assert!(events.contains(&(*at + config.break_duration(), ReplayEvent::BreakEnds)));
+    }
+}
@@ -0,0 +1,226 @@
+//! A dead man's switch for blocked input. The app starts `pomodoro-ss
+//! watchdog` as a child and writes a heartbeat line to its stdin every
+//! second. If the heartbeats stop while a break is being enforced, because
+//! the app deadlocked or was killed outright, the watchdog makes sure the app
+//! is gone, which lets go of its grabs, and starts it again. The running
+//! break is kept in the state file, so the restarted app carries on with it.
+//! Linux only.
+
+use std::{
+    io::{self, BufRead, Write},
+    process::{self, Child, ChildStdin, Stdio},
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use crate::journal::{Journal, JournalEvent};
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heartbeat {
+    Idle,
+    Enforcing,
+    /// The app is shutting down on purpose.
+    Exit,
+}
+
+impl Heartbeat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Heartbeat::Idle => "idle",
+            Heartbeat::Enforcing => "enforcing",
+            Heartbeat::Exit => "exit",
+        }
+    }
+
+    pub fn parse(line: &str) -> Option<Heartbeat> {
+        match line.trim() {
+            "idle" => Some(Heartbeat::Idle),
+            "enforcing" => Some(Heartbeat::Enforcing),
+            "exit" => Some(Heartbeat::Exit),
+            _ => None,
+        }
+    }
+}
+
+/// What the watchdog knows about the app from its heartbeats.
+#[derive(Debug)]
+pub struct Supervisor {
+    timeout: Duration,
+    enforcing: bool,
+    last_beat: Instant,
+}
+
+impl Supervisor {
+    pub fn new(timeout: Duration, now: Instant) -> Supervisor {
+        Supervisor {
+            timeout,
+            enforcing: false,
+            last_beat: now,
+        }
+    }
+
+    pub fn beat(&mut self, heartbeat: Heartbeat, now: Instant) {
+        self.enforcing = heartbeat == Heartbeat::Enforcing;
+        self.last_beat = now;
+    }
+
+    pub fn is_enforcing(&self) -> bool {
+        self.enforcing
+    }
+
+    /// The app went quiet in the middle of enforcing a break. A quiet app
+    /// that isn't enforcing anything can take its time.
+    pub fn is_stalled(&self, now: Instant) -> bool {
+        self.enforcing && now.duration_since(self.last_beat) > self.timeout
+    }
+}
+
+/// Runs the watchdog until the app exits or has to be restarted.
+pub fn run(timeout: Duration) -> Result<()> {
+    let app = std::os::unix::process::parent_id();
+    // Ctrl-C in the terminal reaches both, the app says when it's exiting.
+    // SAFETY: Ignoring a signal installs no handler.
+    unsafe { libc::signal(libc::SIGINT, libc::SIG_IGN) };
+    let (send, heartbeats) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if let Some(heartbeat) = Heartbeat::parse(&line) {
+                if send.send(heartbeat).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let mut supervisor = Supervisor::new(timeout, Instant::now());
+    loop {
+        match heartbeats.recv_timeout(HEARTBEAT_INTERVAL) {
+            Ok(Heartbeat::Exit) => return Ok(()),
+            Ok(heartbeat) => supervisor.beat(heartbeat, Instant::now()),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            // The kernel let go of the grabs along with the app.
+            Err(mpsc::RecvTimeoutError::Disconnected) if supervisor.is_enforcing() => {
+                return restart("the app died while enforcing a break");
+            }
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        if supervisor.is_stalled(Instant::now()) {
+            // SAFETY: Only sends a signal.
+            unsafe { libc::kill(app as libc::pid_t, libc::SIGKILL) };
+            return restart("the app stopped responding while enforcing a break");
+        }
+    }
+}
+
+fn restart(cause: &str) -> Result<()> {
+    eprintln!("watchdog: {cause}, restarting it");
+    if let Some(journal) = Journal::open() {
+        if let Err(e) = journal.record(JournalEvent::EnforcementReleased {
+            cause: format!("watchdog: {cause}"),
+        }) {
+            eprintln!("watchdog: {e:#}");
+        }
+    }
+    let exe = std::env::current_exe()?;
+    process::Command::new(&exe)
+        .stdin(Stdio::null())
+        .spawn()
+        .with_context(|| format!("failed to restart {}", exe.display()))?;
+    Ok(())
+}
+
+/// The app's end of the pipe.
+pub struct Watchdog {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    last_beat: Option<Instant>,
+}
+
+impl Watchdog {
+    pub fn spawn(timeout: Duration) -> Result<Watchdog> {
+        let exe = std::env::current_exe()?;
+        let mut child = process::Command::new(&exe)
+            .arg("watchdog")
+            .arg("--timeout-secs")
+            .arg(timeout.as_secs().to_string())
+            .stdin(Stdio::piped())
+            .spawn()
+            .context("failed to start the watchdog")?;
+        let stdin = child.stdin.take();
+        Ok(Watchdog {
+            child,
+            stdin,
+            last_beat: None,
+        })
+    }
+
+    /// Meant to be called every frame, beats are sent once a
+    /// [`HEARTBEAT_INTERVAL`].
+    pub fn beat(&mut self, enforcing: bool) {
+        if self
+            .last_beat
+            .is_some_and(|last_beat| last_beat.elapsed() < HEARTBEAT_INTERVAL)
+        {
+            return;
+        }
+        self.last_beat = Some(Instant::now());
+        let heartbeat = if enforcing {
+            Heartbeat::Enforcing
+        } else {
+            Heartbeat::Idle
+        };
+        self.send(heartbeat);
+    }
+
+    /// Tells the watchdog right away that nothing is enforced any more, for
+    /// before something that holds up the frame, like a dialog.
+    pub fn idle(&mut self) {
+        self.last_beat = Some(Instant::now());
+        self.send(Heartbeat::Idle);
+    }
+
+    fn send(&mut self, heartbeat: Heartbeat) {
+        let Some(stdin) = self.stdin.as_mut() else {
+            return;
+        };
+        if let Err(e) = writeln!(stdin, "{}", heartbeat.as_str()) {
+            eprintln!("the watchdog is gone: {e}");
+            self.stdin = None;
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.send(Heartbeat::Exit);
+        self.stdin = None;
+        let _res = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stalls_only_while_enforcing() {
+        assert_eq!(Heartbeat::parse("enforcing\n"), Some(Heartbeat::Enforcing));
+        assert_eq!(Heartbeat::parse("beat"), None);
+
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut supervisor = Supervisor::new(Duration::from_secs(5), start);
+        assert!(!supervisor.is_stalled(at(60)));
+        supervisor.beat(Heartbeat::Enforcing, at(60));
+        assert!(!supervisor.is_stalled(at(65)));
+        assert!(supervisor.is_stalled(at(66)));
+        supervisor.beat(Heartbeat::Idle, at(66));
+        assert!(!supervisor.is_stalled(at(120)));
+    }
+}
@@ -0,0 +1,279 @@
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use x11rb::{
+    connection::{Connection, RequestConnection},
+    protocol::{
+        screensaver::{self, ConnectionExt as _},
+        xproto::{AtomEnum, ConnectionExt, Window},
+    },
+    rust_connection::RustConnection,
+};
+
+use crate::{
+    config::PresentationConfig,
+    deferral::{Busy, BusySource},
+    input_source::IdleSource,
+};
+
+x11rb::atom_manager! {
+    Atoms: AtomsCookie {
+        _NET_ACTIVE_WINDOW,
+        _NET_WM_STATE,
+        _NET_WM_STATE_FULLSCREEN,
+    }
+}
+
+/// Busy while the active window is fullscreen or belongs to one of the
+/// configured window classes, which usually means someone is presenting.
+pub struct PresentationDetector {
+    connection: RustConnection,
+    root: Window,
+    atoms: Atoms,
+    detect_fullscreen: bool,
+    window_classes: Vec<String>,
+}
+
+impl PresentationDetector {
+    pub fn connect(detect_fullscreen: bool, window_classes: Vec<String>) -> Result<Self> {
+        let (connection, screen_num) =
+            x11rb::connect(None).context("failed to connect to the X server")?;
+        let root = connection.setup().roots[screen_num].root;
+        let atoms = Atoms::new(&connection)?.reply()?;
+        Ok(PresentationDetector {
+            connection,
+            root,
+            atoms,
+            detect_fullscreen,
+            window_classes: window_classes
+                .into_iter()
+                .map(|c| c.to_lowercase())
+                .collect(),
+        })
+    }
+
+    pub fn from_config(config: &PresentationConfig) -> Result<Self> {
+        PresentationDetector::connect(config.detect_fullscreen, config.window_classes.clone())
+    }
+
+    pub fn active_window(&self) -> Result<Option<Window>> {
+        let reply = self
+            .connection
+            .get_property(
+                false,
+                self.root,
+                self.atoms._NET_ACTIVE_WINDOW,
+                AtomEnum::WINDOW,
+                0,
+                1,
+            )?
+            .reply()?;
+        Ok(reply
+            .value32()
+            .and_then(|mut windows| windows.next())
+            .filter(|&window| window != x11rb::NONE))
+    }
+
+    pub fn is_fullscreen(&self, window: Window) -> Result<bool> {
+        let reply = self
+            .connection
+            .get_property(
+                false,
+                window,
+                self.atoms._NET_WM_STATE,
+                AtomEnum::ATOM,
+                0,
+                u32::MAX,
+            )?
+            .reply()?;
+        let fullscreen = self.atoms._NET_WM_STATE_FULLSCREEN;
+        Ok(reply
+            .value32()
+            .is_some_and(|mut states| states.any(|state| state == fullscreen)))
+    }
+
+    /// The instance and class names from `WM_CLASS`.
+    pub fn window_class(&self, window: Window) -> Result<Vec<String>> {
+        let reply = self
+            .connection
+            .get_property(
+                false,
+                window,
+                AtomEnum::WM_CLASS,
+                AtomEnum::STRING,
+                0,
+                u32::MAX,
+            )?
+            .reply()?;
+        Ok(reply
+            .value
+            .split(|&b| b == 0)
+            .filter(|name| !name.is_empty())
+            .map(|name| String::from_utf8_lossy(name).into_owned())
+            .collect())
+    }
+
+    fn presenting(&self) -> Result<Option<String>> {
+        let Some(window) = self.active_window()? else {
+            return Ok(None);
+        };
+        if let Some(class) = self
+            .window_class(window)?
+            .into_iter()
+            .find(|class| self.window_classes.contains(&class.to_lowercase()))
+        {
+            return Ok(Some(format!("{class} is active")));
+        }
+        if self.detect_fullscreen && self.is_fullscreen(window)? {
+            return Ok(Some("A fullscreen window is active".to_string()));
+        }
+        Ok(None)
+    }
+}
+
+impl BusySource for PresentationDetector {
+    fn busy_at(&mut self, _now: DateTime<Utc>) -> Result<Option<Busy>> {
+        // A display that can't be asked isn't presenting.
+        Ok(self.presenting().ok().flatten().map(|reason| Busy {
+            reason,
+            until: None,
+        }))
+    }
+}
+
+/// Time since the last input as tracked by the X server itself, through the
+/// MIT-SCREEN-SAVER extension. One round trip, no polling of devices.
+pub struct ScreenSaverIdle {
+    connection: RustConnection,
+    root: Window,
+}
+
+impl ScreenSaverIdle {
+    pub fn connect() -> Result<ScreenSaverIdle> {
+        let (connection, screen_num) =
+            x11rb::connect(None).context("failed to connect to the X server")?;
+        if connection
+            .extension_information(screensaver::X11_EXTENSION_NAME)?
+            .is_none()
+        {
+            bail!("the X server doesn't support the MIT-SCREEN-SAVER extension");
+        }
+        let root = connection.setup().roots[screen_num].root;
+        Ok(ScreenSaverIdle { connection, root })
+    }
+
+    pub fn query(&self) -> Result<Duration> {
+        let info = self.connection.screensaver_query_info(self.root)?.reply()?;
+        Ok(Duration::from_millis(info.ms_since_user_input as u64))
+    }
+}
+
+impl IdleSource for ScreenSaverIdle {
+    fn idle_time(&mut self) -> Option<Duration> {
+        self.query().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use x11rb::{
+        protocol::xproto::{CreateWindowAux, PropMode, WindowClass},
+        wrapper::ConnectionExt as _,
+    };
+
+    /// Plays the part of the window manager, which Xvfb doesn't have.
+    fn activate_window(
+        connection: &RustConnection,
+        root: Window,
+        atoms: &Atoms,
+        fullscreen: bool,
+        class: &[u8],
+    ) -> Window {
+        let window = connection.generate_id().unwrap();
+        connection
+            .create_window(
+                x11rb::COPY_DEPTH_FROM_PARENT,
+                window,
+                root,
+                0,
+                0,
+                100,
+                100,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                x11rb::COPY_FROM_PARENT,
+                &CreateWindowAux::new(),
+            )
+            .unwrap();
+        let states: &[u32] = if fullscreen {
+            &[atoms._NET_WM_STATE_FULLSCREEN]
+        } else {
+            &[]
+        };
+        connection
+            .change_property32(
+                PropMode::REPLACE,
+                window,
+                atoms._NET_WM_STATE,
+                AtomEnum::ATOM,
+                states,
+            )
+            .unwrap();
+        connection
+            .change_property8(
+                PropMode::REPLACE,
+                window,
+                AtomEnum::WM_CLASS,
+                AtomEnum::STRING,
+                class,
+            )
+            .unwrap();
+        connection
+            .change_property32(
+                PropMode::REPLACE,
+                root,
+                atoms._NET_ACTIVE_WINDOW,
+                AtomEnum::WINDOW,
+                &[window],
+            )
+            .unwrap();
+        connection.sync().unwrap();
+        window
+    }
+
+    #[test]
+    #[ignore = "needs an X server, run under xvfb-run"]
+    fn detects_fullscreen_and_window_classes() {
+        let mut detector =
+            PresentationDetector::connect(true, vec!["Impress".to_string()]).unwrap();
+        let (connection, screen_num) = x11rb::connect(None).unwrap();
+        let root = connection.setup().roots[screen_num].root;
+        let atoms = Atoms::new(&connection).unwrap().reply().unwrap();
+
+        activate_window(&connection, root, &atoms, false, b"xterm\0XTerm\0");
+        assert_eq!(detector.busy_at(Utc::now()).unwrap(), None);
+
+        activate_window(&connection, root, &atoms, true, b"mpv\0mpv\0");
+        assert!(detector.busy_at(Utc::now()).unwrap().is_some());
+
+        activate_window(&connection, root, &atoms, false, b"libreoffice\0impress\0");
+        assert!(detector.busy_at(Utc::now()).unwrap().is_some());
+    }
+
+    #[test]
+    #[ignore = "needs an X server and xdotool, run under xvfb-run"]
+    fn idle_time_resets_on_input() {
+        let mut idle = ScreenSaverIdle::connect().unwrap();
+        std::thread::sleep(Duration::from_millis(1200));
+        assert!(idle.idle_time().unwrap() >= Duration::from_secs(1));
+
+        let status = std::process::Command::new("xdotool")
+            .args(["mousemove", "10", "10"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert!(idle.idle_time().unwrap() < Duration::from_millis(500));
+    }
+}